@@ -0,0 +1,65 @@
+//! Compares the verification of the signatures of Echo and Ready messages one by one and by batches
+//!
+//! Run with `cargo bench --bench signatures`
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use ed25519_dalek::{Keypair, PublicKey};
+use encoins::crypto::{create_keypair, verif_sigs, SignedMessage};
+use encoins::message::{Message, MessageType};
+use encoins_api::base_types::{Transaction, UserId};
+
+/// Number of servers signing the messages
+const NB_SERVERS: usize = 4;
+
+/// Returns `nb_messages` Echo and Ready messages signed by the servers, with their public keys
+fn signed_messages(nb_messages: usize) -> (Vec<SignedMessage>, Vec<PublicKey>)
+{
+    let servers: Vec<Keypair> = (0..NB_SERVERS).map(|_| create_keypair()).collect();
+    let sender = UserId { id: create_keypair().public };
+    let receiver = UserId { id: create_keypair().public };
+
+    (0..nb_messages)
+        .map(|i|
+        {
+            let keypair = &servers[i % NB_SERVERS];
+            let message = Message
+            {
+                transaction: Transaction::from(i as u32 + 1, sender, receiver, 10),
+                dependencies: vec![],
+                message_type: if i % 2 == 0 { MessageType::Echo } else { MessageType::Ready },
+                sender_id: (i % NB_SERVERS) as u32 + 1,
+                origin: 1,
+                transfer_signature: vec![0; 64],
+                echo_signatures: vec![],
+                origin_signature: vec![],
+                dependencies_root: None,
+                fragment: None,
+            };
+            (message.sign(keypair), keypair.public)
+        })
+        .unzip()
+}
+
+fn verification(c: &mut Criterion)
+{
+    let mut group = c.benchmark_group("signature verification");
+    for nb_messages in [16, 64, 256].iter()
+    {
+        let (messages, public_keys) = signed_messages(*nb_messages);
+        let keys: Vec<&PublicKey> = public_keys.iter().collect();
+
+        group.bench_with_input(BenchmarkId::new("one by one", nb_messages), &messages, |b, messages|
+        {
+            b.iter(|| messages.iter().zip(&public_keys)
+                .all(|(message, public_key)| message.clone().verif_sig(public_key).is_ok()))
+        });
+        group.bench_with_input(BenchmarkId::new("batch", nb_messages), &messages, |b, messages|
+        {
+            b.iter(|| verif_sigs(messages, &keys).iter().all(|result| result.is_ok()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, verification);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+//! Decodes a request sent by a client, and an instruction alone
+#![no_main]
+use encoins_api::instruction::Instruction;
+use libfuzzer_sys::fuzz_target;
+use encoins::errors::decode;
+use encoins::instructions::ClientRequest;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = decode::<ClientRequest>(data)
+    {
+        let _ = request.request.to_string();
+    }
+    if let Ok(instruction) = decode::<Instruction>(data)
+    {
+        let _ = instruction.to_string();
+    }
+});
@@ -0,0 +1,8 @@
+//! Parses the content of a history file
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use encoins::utils::parse_history;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_history(data);
+});
@@ -0,0 +1,11 @@
+//! Parses the content of a seq file
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use encoins::utils::parse_seq;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(content) = std::str::from_utf8(data)
+    {
+        let _ = parse_seq(content);
+    }
+});
@@ -0,0 +1,17 @@
+//! Decodes a message sent by a server and checks its signature
+#![no_main]
+use ed25519_dalek::{PublicKey, SecretKey};
+use libfuzzer_sys::fuzz_target;
+use encoins::crypto::{SignedMessage, verif_sigs};
+use encoins::errors::decode;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(signed_message) = decode::<SignedMessage>(data)
+    {
+        let secret = SecretKey::from_bytes(&[1; 32]).expect("Invalid secret key");
+        let public_key = PublicKey::from(&secret);
+        let _ = signed_message.message.verif_transfer_signature();
+        let _ = verif_sigs(std::slice::from_ref(&signed_message), &[&public_key]);
+        let _ = signed_message.verif_sig(&public_key);
+    }
+});
@@ -2,57 +2,555 @@
 //!
 //! # Warning
 //!
-//! This protocol works only if there are less than 1/3 of the whole process which are byzantine.
-//! If there are more than 1/3 of byzantine process amongst all the process, then the protocol has
+//! This protocol works only if at most `f` of the `n` process are byzantine, with `n >= 3f+1`. The
+//! fault threshold `f` is given by the configuration, and a node refuses to start if `n < 3f+1`.
+//! If there are more than `f` byzantine process amongst all the process, then the protocol has
 //! undefined behavior : it can not terminate or can deliver a wrong message.
 //!
+//! # Thresholds
+//!
+//! As in Bracha's algorithm, a process sends its Ready message once it received `⌈(n+f+1)/2⌉` Echo
+//! messages or `f+1` Ready messages for the same message, and delivers it once it received `2f+1`
+//! Ready messages. When servers have voting weights, `n` is the total weight and `f` the greatest
+//! weight of the byzantine servers.
+//!
 //! # Properties
 //!
-//! This function implement the Byzantine Reliable Broadcast protocol that has the following properties when less than 1/3 of all process are byzantine:
+//! This function implement the Byzantine Reliable Broadcast protocol that has the following properties when at most `f` process are byzantine:
 //! - Validity       : If a correct process `p` broadcast a message `m`, then every correct process eventually delivers `m` ;
 //! - No duplication : Every correct process delivers at most one message ;
 //! - Integrity      : If some correct process delivers a message `m` with sender `p` and process `p` is correct, then `m` was previously broadcast by `p`;
 //! - Consistency    : If some correct process delivers a message `m` and another correct process delivers a message `m'` , then m = `m'`;
 //! - Totality       : If some message is delivered by any correct process, every correct process eventually delivers a message.
+//!
+//! # Other protocols
+//!
+//! Bracha's algorithm costs `O(n²)` messages per transaction. A network can instead use the consistent
+//! broadcast of [`crate::signed_echo`], which costs `O(n)` messages but does not ensure totality when the
+//! origin is byzantine, or the gossip broadcast of [`crate::gossip`], which costs `O(n log n)` messages but
+//! only ensures the properties with a high probability. The dispersal broadcast of [`crate::dispersal`]
+//! keeps the messages of Bracha's algorithm but sends fragments of the dependencies instead of the whole
+//! dependencies. They all implement [`BroadcastInstance`], and the
+//! configuration tells which one is used.
 
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use sha2::{Digest as _, Sha256};
+use encoins_api::base_types::{SeqId, UserId};
+use crate::certificate::DeliveryCertificate;
+use crate::crypto::SignedMessage;
+use crate::dispersal::{Digest, DispersalBroadcast};
+use crate::gossip::{GossipBroadcast, GossipParameters};
+use crate::log;
 use crate::message::{Message, MessageType};
+use crate::process::ProcId;
+use crate::signed_echo::SignedEchoBroadcast;
+
+/// Broadcast protocols a network can use, given by the configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BroadcastKind
+{
+    /// Bracha's Byzantine Reliable Broadcast, see [`Broadcast`]
+    Reliable,
+    /// Signed echo consistent broadcast, see [`SignedEchoBroadcast`]
+    Consistent,
+    /// Sample-based gossip broadcast, see [`GossipBroadcast`]
+    Gossip(GossipParameters),
+    /// Bracha's broadcast with erasure-coded dependencies, see [`DispersalBroadcast`]
+    Dispersal,
+}
+
+impl BroadcastKind
+{
+    /// Reads a kind of broadcast from its name, `reliable`, `consistent`, `gossip` or `dispersal`. A gossip broadcast
+    /// gets the default parameters of a network of `nb_servers` servers
+    pub fn from_name(name: &str, nb_servers: usize) -> Result<BroadcastKind, String>
+    {
+        match name
+        {
+            "reliable" => { Ok(BroadcastKind::Reliable) }
+            "consistent" => { Ok(BroadcastKind::Consistent) }
+            "gossip" => { Ok(BroadcastKind::Gossip(GossipParameters::for_network(nb_servers))) }
+            "dispersal" => { Ok(BroadcastKind::Dispersal) }
+            _ => { Err(format!("Unknown broadcast {}", name)) }
+        }
+    }
+
+    /// States whether the certificates of the broadcast prove the delivery of a transaction to anyone
+    /// knowing the public keys of the servers. The ones of a gossip broadcast only hold the signatures
+    /// of the sample of a server
+    pub fn proves_delivery(&self) -> bool
+    {
+        !matches!(self, BroadcastKind::Gossip(_))
+    }
+}
+
+impl Display for BroadcastKind
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            BroadcastKind::Reliable => { write!(f, "reliable") }
+            BroadcastKind::Consistent => { write!(f, "consistent") }
+            BroadcastKind::Dispersal => { write!(f, "dispersal") }
+            BroadcastKind::Gossip(parameters) =>
+            {
+                write!(f, "gossip (samples of {}, {}, {} and {} process)", parameters.gossip_sample,
+                    parameters.echo_sample, parameters.ready_sample, parameters.delivery_sample)
+            }
+        }
+    }
+}
+
+/// What a process taking part to a broadcast has to do
+#[derive(Debug)]
+pub enum Action
+{
+    /// Sign the message and send it to every server, the process included
+    Broadcast(Message),
+    /// Sign the message and send it to the given server
+    SendTo(ProcId, Message),
+    /// Deliver the message, whose delivery is proven by the certificate
+    Deliver(Message, Box<DeliveryCertificate>),
+}
+
+/// An instance of a broadcast protocol, started by the Init message of a transaction. An instance only
+/// decides what the process has to do: the process signs and sends the messages, and checks the
+/// signatures of the messages it gives to the instance
+pub trait BroadcastInstance
+{
+    /// Returns what to do once the Init message of the broadcast was received and checked, given with
+    /// the signature of its origin
+    fn start(&mut self, init : &Message, origin_signature : &[u8]) -> Vec<Action>;
+
+    /// Deals with a message of the broadcast, with its signature, and returns what to do
+    fn add_message(&mut self, message : Message, signature : Vec<u8>) -> Vec<Action>;
+
+    /// Returns the instant the broadcast started
+    fn started(&self) -> Instant;
+
+    /// States whether the message of the broadcast was delivered
+    fn is_delivered(&self) -> bool;
+
+    /// Returns why the broadcast is not delivered yet
+    fn stuck_reason(&self) -> &'static str;
+}
+
+/// Identifies a broadcast instance: the transaction `seq_id` of `account`, whichever process started it.
+/// A process thus echoes at most one transaction per sequence number of an account, and several
+/// transactions of the same account can be broadcast at the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BroadcastId
+{
+    /// Account sending the money
+    pub account : UserId,
+    /// Sequence number of the transaction for the account
+    pub seq_id : SeqId,
+}
+
+/// Maximal number of messages of a process kept for broadcasts whose Init was not received yet
+pub const EARLY_MESSAGES_PER_SENDER : usize = 1024;
+/// Maximal size of the messages of a process kept for broadcasts whose Init was not received yet (4 MiB)
+pub const EARLY_BYTES_PER_SENDER : usize = 4 * 1024 * 1024;
+
+/// Broadcasts a process takes part to.
+///
+/// On a real network, the Echo and Ready messages of fast servers can arrive before the Init message
+/// starting their broadcast. They are kept aside, up to [`EARLY_MESSAGES_PER_SENDER`] messages and
+/// [`EARLY_BYTES_PER_SENDER`] bytes per sending process, and given back once the broadcast starts. Since
+/// messages are signed by their sender, a byzantine process can only fill its own share.
+///
+/// A broadcast whose origin is faulty or whose messages were lost may never be delivered. Broadcasts
+/// and early messages are thus forgotten once they are older than a lifetime given by the configuration.
+/// Delivered broadcasts are kept until then too, so that the process still answers the processes which
+/// did not deliver yet, and does not take their late messages for early ones.
+///
+/// The transactions the process echoed are remembered until they are validated, even once their broadcast
+/// is forgotten: a transaction can then be broadcast again, but not replaced by another one with the same
+/// sequence number
+pub struct OngoingBroadcasts
+{
+    /// Broadcasts whose Init message was received, delivered or not
+    broadcasts : HashMap<BroadcastId, Box<dyn BroadcastInstance>>,
+    /// Digest of the transaction of each broadcast the process started, until the transaction is validated
+    echoed : HashMap<BroadcastId, Digest>,
+    /// Messages received before the Init message of their broadcast, by sender
+    early_messages : BTreeMap<ProcId, EarlyMessages>,
+    /// Number of messages kept before the Init message of their broadcast so far, which orders them
+    nb_early_messages : u64,
+    /// Time after which a broadcast is forgotten
+    lifetime : Duration,
+    /// Source of the samples of gossip broadcasts
+    rng : StdRng,
+}
+
+impl OngoingBroadcasts
+{
+    pub fn new(lifetime : Duration) -> OngoingBroadcasts
+    {
+        OngoingBroadcasts
+        {
+            broadcasts : HashMap::new(),
+            echoed : HashMap::new(),
+            early_messages : BTreeMap::new(),
+            nb_early_messages : 0,
+            lifetime,
+            rng : StdRng::from_entropy(),
+        }
+    }
+
+    /// Draws the samples of gossip broadcasts from the given seed, so that they can be drawn again
+    pub fn with_seed(self, seed : u64) -> OngoingBroadcasts
+    {
+        OngoingBroadcasts { rng : StdRng::seed_from_u64(seed), ..self }
+    }
+
+    /// Creates an instance of the given protocol, for process `proc_id` taking part to a broadcast started
+    /// by process `origin`
+    pub fn new_instance(&mut self, kind : BroadcastKind, proc_id : ProcId, origin : ProcId, weights : Weights)
+        -> Box<dyn BroadcastInstance>
+    {
+        match kind
+        {
+            BroadcastKind::Reliable => { Box::new(init_broadcast(proc_id, weights)) }
+            BroadcastKind::Consistent => { Box::new(SignedEchoBroadcast::new(proc_id, origin, weights)) }
+            BroadcastKind::Gossip(parameters) =>
+            {
+                Box::new(GossipBroadcast::new(proc_id, parameters, &weights.servers(), &mut self.rng))
+            }
+            BroadcastKind::Dispersal => { Box::new(DispersalBroadcast::new(proc_id, weights)) }
+        }
+    }
+
+    pub fn contains_key(&self, id : &BroadcastId) -> bool
+    {
+        self.broadcasts.contains_key(id)
+    }
+
+    pub fn get_mut(&mut self, id : &BroadcastId) -> Option<&mut Box<dyn BroadcastInstance>>
+    {
+        self.broadcasts.get_mut(id)
+    }
+
+    pub fn insert(&mut self, id : BroadcastId, broadcast : Box<dyn BroadcastInstance>)
+    {
+        self.broadcasts.insert(id, broadcast);
+    }
+
+    /// Records the transaction of a broadcast the process starts. Returns false if the process already
+    /// started a broadcast of another transaction with the same sequence number, in which case it must not
+    /// take part to this one
+    pub fn record_init(&mut self, init : &Message) -> bool
+    {
+        let serialized = bincode::serialize(&init.transaction).expect("Problem with the serialization of a transaction");
+        let digest : Digest = Sha256::digest(&serialized).into();
+        *self.echoed.entry(BroadcastId::of(init)).or_insert(digest) == digest
+    }
+
+    /// Forgets what the process echoed for the transactions which are validated, given the sequence
+    /// number of the last validated transaction of each account. Returns the ids of their broadcasts
+    pub fn forget_validated<F>(&mut self, last_validated : F) -> Vec<BroadcastId> where F : Fn(&UserId) -> Option<SeqId>
+    {
+        let mut forgotten = Vec::new();
+        self.echoed.retain(|id, _|
+        {
+            let validated = match last_validated(&id.account)
+            {
+                Some(seq_id) => { id.seq_id <= seq_id }
+                None => { false }
+            };
+            if validated
+            {
+                forgotten.push(*id);
+            }
+            !validated
+        });
+        forgotten
+    }
+
+    pub fn remove(&mut self, id : &BroadcastId) -> Option<Box<dyn BroadcastInstance>>
+    {
+        self.broadcasts.remove(id)
+    }
+
+    /// Keeps a message whose broadcast did not start yet. If too many messages are kept for the sender of
+    /// the message, the oldest ones are dropped and returned
+    pub fn keep_early_message(&mut self, signed_message : SignedMessage) -> Vec<SignedMessage>
+    {
+        let early_messages = self.early_messages.entry(signed_message.message.sender_id).or_default();
+        let size = bincode::serialized_size(&signed_message).map_or(usize::MAX, |size| size as usize);
+        early_messages.messages.push_back((Instant::now(), self.nb_early_messages, size, signed_message));
+        self.nb_early_messages += 1;
+        early_messages.size = early_messages.size.saturating_add(size);
+
+        let mut dropped = Vec::new();
+        while early_messages.messages.len() > EARLY_MESSAGES_PER_SENDER || early_messages.size > EARLY_BYTES_PER_SENDER
+        {
+            match early_messages.messages.pop_front()
+            {
+                Some((_, _, size, signed_message)) =>
+                {
+                    early_messages.size -= size;
+                    dropped.push(signed_message);
+                }
+                None => { break; }
+            }
+        }
+        dropped
+    }
+
+    /// Removes and returns the messages kept for the given broadcast, in the order they were received
+    pub fn take_early_messages(&mut self, id : &BroadcastId) -> Vec<SignedMessage>
+    {
+        let mut taken: Vec<(u64, SignedMessage)> = self.early_messages.values_mut()
+            .flat_map(|early_messages| early_messages.take(|_, signed_message| BroadcastId::of(&signed_message.message) == *id))
+            .collect();
+        self.early_messages.retain(|_, early_messages| !early_messages.messages.is_empty());
+
+        taken.sort_by_key(|(arrival, _)| *arrival);
+        taken.into_iter().map(|(_, signed_message)| signed_message).collect()
+    }
+
+    /// Removes the broadcasts started more than a lifetime ago, and returns their ids, with the reason why
+    /// for the ones which were not delivered. Early messages received more than a lifetime ago are
+    /// dropped too
+    pub fn evict_expired(&mut self, now : Instant) -> Vec<(BroadcastId, Option<&'static str>)>
+    {
+        let lifetime = self.lifetime;
+        let expired = |started : &Instant| now.saturating_duration_since(*started) > lifetime;
+
+        let mut evicted = Vec::new();
+        self.broadcasts.retain(|id, broadcast|
+        {
+            if !expired(&broadcast.started())
+            {
+                return true;
+            }
+            match broadcast.is_delivered()
+            {
+                true => { evicted.push((*id, None)); }
+                false => { evicted.push((*id, Some(broadcast.stuck_reason()))); }
+            }
+            false
+        });
+
+        for early_messages in self.early_messages.values_mut()
+        {
+            early_messages.take(|received, _| expired(received));
+        }
+        self.early_messages.retain(|_, early_messages| !early_messages.messages.is_empty());
+
+        evicted
+    }
+}
+
+/// Messages a process sent for broadcasts whose Init was not received yet
+#[derive(Default)]
+struct EarlyMessages
+{
+    /// Messages, oldest first, with the instant they were received, their rank amongst the early messages
+    /// of every process, and their size
+    messages : VecDeque<(Instant, u64, usize, SignedMessage)>,
+    /// Total size of the messages
+    size : usize,
+}
+
+impl EarlyMessages
+{
+    /// Removes and returns the messages which satisfy the predicate, with their rank
+    fn take<F>(&mut self, predicate : F) -> Vec<(u64, SignedMessage)> where F : Fn(&Instant, &SignedMessage) -> bool
+    {
+        let (taken, kept) : (VecDeque<_>, VecDeque<_>) = self.messages.drain(..)
+            .partition(|(received, _, _, signed_message)| predicate(received, signed_message));
+        self.messages = kept;
+        self.size -= taken.iter().map(|(_, _, size, _)| size).sum::<usize>();
+        taken.into_iter().map(|(_, arrival, _, signed_message)| (arrival, signed_message)).collect()
+    }
+}
+
+impl BroadcastId
+{
+    /// Returns the id of the broadcast the given message belongs to
+    pub fn of(message : &Message) -> BroadcastId
+    {
+        BroadcastId
+        {
+            account : message.transaction.sender_id,
+            seq_id : message.transaction.seq_id,
+        }
+    }
+}
+
+impl Display for BroadcastId
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "transaction {} of {}", self.seq_id, self.account)
+    }
+}
 
 pub struct Broadcast
 {
-    /// Received echo messages
+    /// Received echo messages, by index of their sender
     echos: Vec<Option<Message>>,
-    /// Received ready messages
+    /// Received ready messages, by index of their sender
     ready: Vec<Option<Message>>,
-    /// Nb of process involved in the broadcast
-    nb_procs : usize,
-    /// Id of the process owning the broadcast
-    proc_id: usize,
+    /// Signatures of the received ready messages, by index of their sender
+    ready_signatures: Vec<Option<Vec<u8>>>,
+    /// Index of the messages of each process involved in the broadcast
+    indices : HashMap<ProcId, usize>,
+    /// Process whose messages are at each index
+    servers : Vec<ProcId>,
+    /// Weights of the processes involved in the broadcast
+    weights : Weights,
+    /// Id of the process taking part to the broadcast
+    proc_id : ProcId,
     /// Variable stating if a quorum was achieved
     quorum_achieved : bool,
     /// Variable stating if the process owning the broadcast is ready to send the ready message
     is_ready : bool,
     /// Variable stating if a ready message was already sent
-    ready_message_sent : bool
+    ready_message_sent : bool,
+    /// Variable stating if the message was already delivered
+    delivered : bool,
+    /// Instant the broadcast started
+    started : Instant,
+}
+
+/// Voting weight of a server
+pub type Weight = u64;
+
+/// Voting weights of the servers, declared in the configuration of the network, with the greatest
+/// weight of the byzantine servers the network tolerates. The quorums of a broadcast are computed over
+/// the weights, so that the operators of more trusted servers can be given more say
+#[derive(Clone, Debug)]
+pub struct Weights
+{
+    /// The i-th weight is the one of server i+1
+    weights : Vec<Weight>,
+    /// Weight of all the servers
+    total : Weight,
+    /// Greatest weight of the byzantine servers
+    fault_threshold : Weight,
+}
+
+impl Weights
+{
+    /// Creates the weights of the servers, the i-th weight being the one of server i+1. The network
+    /// tolerates the greatest fault threshold `f` such that the total weight is at least `3f+1`.
+    /// Fails if the total weight does not fit in a [`Weight`]
+    pub fn from(weights : Vec<Weight>) -> Result<Weights, String>
+    {
+        let total = weights.iter()
+            .try_fold(0 as Weight, |total, weight| total.checked_add(*weight))
+            .ok_or_else(|| String::from("The total weight of the servers is too large"))?;
+        Ok(Weights { weights, total, fault_threshold : total.saturating_sub(1) / 3 })
+    }
+
+    /// Gives the same weight to the `nb_servers` servers
+    pub fn equal(nb_servers : usize) -> Weights
+    {
+        Weights::from(vec![1; nb_servers]).expect("Servers weighing 1 can not overflow the total weight")
+    }
+
+    /// Sets the greatest weight of the byzantine servers. Fails if the total weight is less than `3f+1`,
+    /// since the broadcast can not be safe then
+    pub fn with_fault_threshold(self, fault_threshold : Weight) -> Result<Weights, String>
+    {
+        let needed = fault_threshold.checked_mul(3).and_then(|weight| weight.checked_add(1));
+        match needed
+        {
+            Some(needed) if self.total >= needed => { Ok(Weights { fault_threshold, ..self }) }
+            Some(needed) => { Err(format!("A network of weight {} can not tolerate byzantine servers of weight {}, \
+                it needs a weight of at least 3f+1 = {}", self.total, fault_threshold, needed)) }
+            None => { Err(format!("A network of weight {} can not tolerate byzantine servers of weight {}",
+                self.total, fault_threshold)) }
+        }
+    }
+
+    /// Returns the ids of the servers
+    pub fn servers(&self) -> Vec<ProcId>
+    {
+        (1..=self.weights.len() as ProcId).collect()
+    }
+
+    /// Returns the weight of server `id`, which is 0 if there is no such server
+    pub fn of(&self, id : ProcId) -> Weight
+    {
+        match id
+        {
+            0 => { 0 }
+            _ => { self.weights.get(id as usize - 1).cloned().unwrap_or(0) }
+        }
+    }
+
+    /// Returns the weight of all the servers
+    pub fn total(&self) -> Weight
+    {
+        self.total
+    }
+
+    /// Returns the greatest weight of the byzantine servers
+    pub fn fault_threshold(&self) -> Weight
+    {
+        self.fault_threshold
+    }
+
+    /// Returns the weight of the Echo messages after which a process sends its Ready message, `⌈(n+f+1)/2⌉`,
+    /// computed so that it does not overflow since `f < n`
+    pub fn echo_quorum(&self) -> Weight
+    {
+        (self.total - self.fault_threshold) / 2 + self.fault_threshold + 1
+    }
+
+    /// Returns the weight of the Ready messages after which a process sends its own, `f+1`
+    pub fn ready_quorum(&self) -> Weight
+    {
+        self.fault_threshold + 1
+    }
+
+    /// Returns the weight of the Ready messages proving the delivery of a message, `2f+1`
+    pub fn delivery_quorum(&self) -> Weight
+    {
+        2 * self.fault_threshold + 1
+    }
 }
 
-pub fn init_broadcast(proc_numb : usize ,nb_involved : usize) -> Broadcast
+pub fn init_broadcast(proc_id : ProcId, weights : Weights) -> Broadcast
 {
+    let servers = weights.servers();
     Broadcast
     {
-        echos: vec![None; nb_involved],
-        ready: vec![None; nb_involved],
-        nb_procs : nb_involved,
-        proc_id: proc_numb,
+        echos: vec![None; servers.len()],
+        ready: vec![None; servers.len()],
+        ready_signatures: vec![None; servers.len()],
+        indices : servers.iter().enumerate().map(|(index, id)| (*id, index)).collect(),
+        servers,
+        weights,
+        proc_id,
         quorum_achieved : false,
         is_ready : false,
         ready_message_sent : false,
+        delivered : false,
+        started : Instant::now(),
     }
 }
 
 impl Broadcast
 {
-    pub fn add_message(& mut self, message : Message) -> String
+    /// Adds a received message, with its signature, to the broadcast
+    fn record_message(& mut self, message : Message, signature : Vec<u8>) -> String
     {
+        let index = match self.indices.get(&message.sender_id)
+        {
+            Some(index) => { *index }
+            None => { return format!("Received a message from {} which is not involved in the broadcast", message.sender_id) }
+        };
+
         match message.message_type
         {
             MessageType::Init =>
@@ -61,48 +559,37 @@ impl Broadcast
             }
             MessageType::Echo =>
             {
-                self.echos[message.sender_id as usize] = Some(message.clone());
+                self.echos[index] = Some(message.clone());
                 self.update_broadcast(&message);
-                String::from(format!("Received an echo message from {}", message.sender_id))
+                format!("Received an echo message from {}", message.sender_id)
             }
             MessageType::Ready =>
             {
-                self.ready[message.sender_id as usize] = Some(message.clone());
+                self.ready[index] = Some(message.clone());
+                self.ready_signatures[index] = Some(signature);
                 self.update_broadcast(&message);
-                String::from(format!("Received a ready message from {}", message.sender_id))
+                format!("Received a ready message from {}", message.sender_id)
+            }
+            MessageType::Final | MessageType::EchoSubscribe | MessageType::ReadySubscribe =>
+            {
+                format!("Received a {} message which is not part of a reliable broadcast!", message.message_type)
             }
         }
     }
 
     fn update_broadcast(& mut self, message : &Message)
     {
-        // If the broadcast was not ready, check if it is now
+        // If the broadcast was not ready, check if it is now. Enough Ready messages prove that a correct
+        // process received enough Echo messages
         if !self.is_ready
         {
-            match self.ready[self.proc_id]
-            {
-                None =>
-                {
-                    let k = (2 * self.nb_procs) / 3;
-                    if nb_occs(&self.echos, message) > k
-                    {
-                        self.is_ready = true;
-                        self.ready[self.proc_id] = Some(message.clone());
-                    }
-                }
-                Some(_) =>
-                {
-                    let k = self.nb_procs / 3;
-                    if nb_occs(&self.ready, message) > k
-                    {
-                        self.is_ready = true;
-                        self.ready[self.proc_id] = Some(message.clone());
-                    }
-                }
-            };
+            self.is_ready = self.weight_of(&self.echos, message) >= self.weights.echo_quorum()
+                || self.weight_of(&self.ready, message) >= self.weights.ready_quorum();
         }
 
-        self.quorum_achieved = nb_occs(&self.ready, message) > (2*self.nb_procs)/3;
+        // Only signed ready messages count, so that the delivery can be proven with a certificate
+        let signers_weight : Weight = self.ready_signers(message).iter().map(|(id, _)| self.weights.of(*id)).sum();
+        self.quorum_achieved = signers_weight >= self.weights.delivery_quorum();
 
     }
 
@@ -126,26 +613,212 @@ impl Broadcast
         self.quorum_achieved
     }
 
+    /// Returns the weight of the processes whose message in the given vector is the given [`Message`]
+    fn weight_of(&self, tab : &[Option<Message>], ref_msg : &Message) -> Weight
+    {
+        let mut weight = 0;
+        for (id, opt_mes) in self.servers.iter().zip(tab.iter())
+        {
+            if let Some(message) = opt_mes
+            {
+                if ref_msg == message
+                {
+                    weight += self.weights.of(*id);
+                }
+            }
+        }
+        weight
+    }
 
-}
+    /// Returns the certificate proving the delivery of the given message
+    pub fn certificate(&self, message : &Message) -> DeliveryCertificate
+    {
+        DeliveryCertificate::from(message, MessageType::Ready, self.ready_signers(message))
+    }
 
-/// Returns the number of occurrences of the given [`Message`] in a vector of messages
-fn nb_occs(tab: &Vec<Option<Message>>, ref_msg: &Message) -> usize
-{
-    let mut nb_occs = 0;
-    for opt_mes in tab
+    /// Returns the processes which sent a signed ready message for the given message, with their signature
+    fn ready_signers(&self, message : &Message) -> Vec<(ProcId, Vec<u8>)>
     {
-        match opt_mes
+        let mut signers = Vec::new();
+        for (id, (ready, signature)) in self.servers.iter().zip(self.ready.iter().zip(self.ready_signatures.iter()))
         {
-            None => {}
-            Some(message) =>
+            if let (Some(ready), Some(signature)) = (ready, signature)
             {
-                if ref_msg == message
+                if ready == message
                 {
-                    nb_occs +=1;
+                    signers.push((*id, signature.clone()));
                 }
             }
         }
+        signers
+    }
+}
+
+impl BroadcastInstance for Broadcast
+{
+    fn start(&mut self, init : &Message, _origin_signature : &[u8]) -> Vec<Action>
+    {
+        // Echo the message
+        let mut echo_msg = init.clone();
+        echo_msg.sender_id = self.proc_id;
+        echo_msg.message_type = MessageType::Echo;
+        log!("Broadcasting echo message to everyone!");
+        vec![Action::Broadcast(echo_msg)]
+    }
+
+    fn add_message(&mut self, message : Message, signature : Vec<u8>) -> Vec<Action>
+    {
+        log!("{}", self.record_message(message.clone(), signature));
+        let mut actions = Vec::new();
+
+        if self.is_ready && !self.ready_message_sent
+        {
+            log!("I am ready to accept a message. Broadcasting it to everyone.");
+            self.ready_message_sent = true;
+            let mut ready_msg = message.clone();
+            ready_msg.sender_id = self.proc_id;
+            ready_msg.message_type = MessageType::Ready;
+            actions.push(Action::Broadcast(ready_msg));
+        }
+
+        if self.quorum_achieved && !self.delivered
+        {
+            log!("Quorum was achieved. I can add the message to transactions to process.");
+            self.delivered = true;
+            let certificate = self.certificate(&message);
+            actions.push(Action::Deliver(message, Box::new(certificate)));
+        }
+        actions
+    }
+
+    fn started(&self) -> Instant
+    {
+        self.started
+    }
+
+    fn is_delivered(&self) -> bool
+    {
+        self.delivered
+    }
+
+    fn stuck_reason(&self) -> &'static str
+    {
+        match self.is_ready
+        {
+            true => { "not enough ready messages" }
+            false => { "not enough echo messages" }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use encoins_api::base_types::Transaction;
+    use crate::test_utils;
+
+    #[test]
+    fn quorums_are_the_ones_of_bracha()
+    {
+        let weights = Weights::equal(4);
+        assert_eq!((weights.fault_threshold(), weights.echo_quorum(), weights.ready_quorum(), weights.delivery_quorum()),
+            (1, 3, 2, 3));
+
+        let weights = Weights::from(vec![1, 2, 1, 3]).unwrap().with_fault_threshold(2).unwrap();
+        assert_eq!((weights.echo_quorum(), weights.ready_quorum(), weights.delivery_quorum()), (5, 3, 5));
+        assert!(Weights::equal(6).with_fault_threshold(2).is_err());
+    }
+
+    #[test]
+    fn overflowing_weights_are_rejected()
+    {
+        assert!(Weights::from(vec![Weight::MAX, 1]).is_err());
+        assert!(Weights::equal(4).with_fault_threshold(Weight::MAX / 3).is_err());
+        assert!(Weights::equal(4).with_fault_threshold(Weight::MAX).is_err());
+
+        let weights = Weights::from(vec![Weight::MAX]).unwrap();
+        assert_eq!(weights.echo_quorum(), Weight::MAX / 3 * 2);
+    }
+
+    /// Returns the Init message of transaction `seq_id` of the test account, sending `amount` and started by `origin`
+    fn init_message(seq_id : SeqId, amount : u32, origin : ProcId) -> Message
+    {
+        let mut message = test_utils::message(MessageType::Init, origin, seq_id, amount);
+        message.origin = origin;
+        message
+    }
+
+    #[test]
+    fn one_message_is_echoed_per_transaction_until_it_is_validated()
+    {
+        let account = test_utils::account();
+        let mut ongoing_broadcasts = OngoingBroadcasts::new(Duration::from_secs(30));
+
+        assert!(ongoing_broadcasts.record_init(&init_message(1, 10, 1)));
+        // The same transaction can be started again, by another process or with other dependencies, but
+        // not replaced
+        let mut with_dependencies = init_message(1, 10, 2);
+        with_dependencies.dependencies = vec![Transaction::from(1, account, account, 5)];
+        assert!(ongoing_broadcasts.record_init(&with_dependencies));
+        assert!(!ongoing_broadcasts.record_init(&init_message(1, 20, 1)));
+        assert!(ongoing_broadcasts.record_init(&init_message(2, 20, 1)));
+
+        assert!(ongoing_broadcasts.forget_validated(|_| None).is_empty());
+        assert!(!ongoing_broadcasts.record_init(&init_message(1, 20, 1)));
+        assert_eq!(ongoing_broadcasts.forget_validated(|_| Some(1)), vec![BroadcastId { account, seq_id : 1 }]);
+        assert!(ongoing_broadcasts.record_init(&init_message(1, 20, 1)));
+        assert!(!ongoing_broadcasts.record_init(&init_message(2, 10, 1)));
+    }
+
+    /// Returns the Echo message `sender` sends for transaction `seq_id` of the test account
+    fn echo_message(seq_id : SeqId, sender : ProcId) -> SignedMessage
+    {
+        SignedMessage { message : test_utils::message(MessageType::Echo, sender, seq_id, 10), signature : vec![] }
+    }
+
+    #[test]
+    fn early_messages_are_given_back_in_the_order_they_were_received()
+    {
+        let mut ongoing_broadcasts = OngoingBroadcasts::new(Duration::from_secs(30));
+        for (seq_id, sender) in [(1, 3), (1, 2), (2, 2), (1, 4), (1, 2)]
+        {
+            assert!(ongoing_broadcasts.keep_early_message(echo_message(seq_id, sender)).is_empty());
+        }
+
+        let id = BroadcastId { account : test_utils::account(), seq_id : 1 };
+        assert_eq!(ongoing_broadcasts.take_early_messages(&id).iter().map(|taken| taken.message.sender_id).collect::<Vec<_>>(),
+            vec![3, 2, 4, 2]);
+        assert!(ongoing_broadcasts.take_early_messages(&id).is_empty());
+    }
+
+    #[test]
+    fn early_messages_are_bounded_per_sender()
+    {
+        let account = test_utils::account();
+        let mut ongoing_broadcasts = OngoingBroadcasts::new(Duration::from_secs(30));
+
+        // A process flooding early messages only pushes out its own ones
+        assert!(ongoing_broadcasts.keep_early_message(echo_message(1, 2)).is_empty());
+        for seq_id in 1..=EARLY_MESSAGES_PER_SENDER as SeqId
+        {
+            assert!(ongoing_broadcasts.keep_early_message(echo_message(seq_id, 3)).is_empty());
+        }
+        let dropped = ongoing_broadcasts.keep_early_message(echo_message(0, 3));
+        assert_eq!(dropped.iter().map(|dropped| (dropped.message.sender_id, dropped.message.transaction.seq_id)).collect::<Vec<_>>(),
+            vec![(3, 1)]);
+        let id = BroadcastId { account, seq_id : 1 };
+        assert_eq!(ongoing_broadcasts.take_early_messages(&id).iter().map(|taken| taken.message.sender_id).collect::<Vec<_>>(),
+            vec![2]);
+
+        // Large messages are bounded by their size
+        let mut large = echo_message(1, 4);
+        large.message.dependencies = vec![Transaction::from(1, account, account, 5); 10_000];
+        let size = bincode::serialized_size(&large).unwrap() as usize;
+        for _ in 0..EARLY_BYTES_PER_SENDER / size
+        {
+            assert!(ongoing_broadcasts.keep_early_message(large.clone()).is_empty());
+        }
+        assert_eq!(ongoing_broadcasts.keep_early_message(large).len(), 1);
     }
-    nb_occs
-}
\ No newline at end of file
+}
@@ -0,0 +1,263 @@
+//! Byzantine behaviours a node can be started with
+//!
+//! A correct node follows the protocol, but the Byzantine Reliable Broadcast is meant to tolerate
+//! up to f < n/3 nodes behaving arbitrarily. To show it does, a node can be started in one of the
+//! following modes, which only change how it reacts to the messages of the other servers and to the
+//! transfers asked by its clients:
+//! - `silent`          : the node never takes part to the protocol ;
+//! - `crash-after:<k>` : the node behaves correctly for its first `k` messages, then stops ;
+//! - `equivocate`      : the node waits for its client to sign two transactions with the same sequence
+//!   number, then sends the Init message of each to a different half of the servers ;
+//! - `forge-echo`      : the node echoes forged transactions instead of the ones it receives ;
+//! - `eager-ready`     : the node sends Ready messages without waiting for a quorum of echoes.
+//!
+//! The mode of a real node is read from the `BYZANTINE_MODE` environment variable.
+
+use std::fmt::{Display, Formatter};
+use encoins_api::base_types::Transaction;
+use crate::broadcast::OngoingBroadcasts;
+use crate::crypto::SignedMessage;
+use crate::log;
+use crate::message::{Message, MessageType};
+use crate::messaging;
+use crate::process::Process;
+
+/// Name of the environment variable giving the mode of a node
+pub const BYZANTINE_MODE_VARIABLE: &str = "BYZANTINE_MODE";
+
+/// How a node behaves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Behaviour
+{
+    /// The node follows the protocol
+    Correct,
+    /// The node ignores every message and transfer
+    Silent,
+    /// The node follows the protocol for the given number of messages, then stops
+    CrashAfter(usize),
+    /// The node sends two conflicting transactions signed by a client to different servers
+    Equivocate,
+    /// The node echoes forged transactions
+    ForgeEcho,
+    /// The node sends a Ready message as soon as it receives an Init message
+    EagerReady,
+}
+
+impl Behaviour
+{
+    /// Reads a behaviour from its name, as listed in the documentation of the module
+    pub fn from_name(name: &str) -> Result<Behaviour, String>
+    {
+        match name
+        {
+            "correct" => { Ok(Behaviour::Correct) }
+            "silent" => { Ok(Behaviour::Silent) }
+            "equivocate" => { Ok(Behaviour::Equivocate) }
+            "forge-echo" => { Ok(Behaviour::ForgeEcho) }
+            "eager-ready" => { Ok(Behaviour::EagerReady) }
+            _ =>
+            {
+                match name.strip_prefix("crash-after:").map(|k| k.parse::<usize>())
+                {
+                    Some(Ok(k)) => { Ok(Behaviour::CrashAfter(k)) }
+                    _ => { Err(format!("Unknown byzantine mode {}", name)) }
+                }
+            }
+        }
+    }
+
+    /// States whether the node follows the protocol
+    pub fn is_correct(&self) -> bool
+    {
+        *self == Behaviour::Correct
+    }
+}
+
+impl Display for Behaviour
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            Behaviour::Correct => { write!(f, "correct") }
+            Behaviour::Silent => { write!(f, "silent") }
+            Behaviour::CrashAfter(k) => { write!(f, "crash-after:{}", k) }
+            Behaviour::Equivocate => { write!(f, "equivocate") }
+            Behaviour::ForgeEcho => { write!(f, "forge-echo") }
+            Behaviour::EagerReady => { write!(f, "eager-ready") }
+        }
+    }
+}
+
+/// Checks the signatures of messages of other servers, then deals with the valid ones according to
+/// the behaviour of the node
+pub fn deal_with_messages(process: &mut Process, signed_messages: Vec<SignedMessage>,
+    ongoing_broadcasts: &mut OngoingBroadcasts)
+{
+    for signed_message in messaging::verify_messages(process, signed_messages)
+    {
+        deal_with_message(process, signed_message, ongoing_broadcasts);
+    }
+}
+
+/// Deals with a message of another server, whose signature was checked, according to the behaviour of the node
+fn deal_with_message(process: &mut Process, signed_message: SignedMessage,
+    ongoing_broadcasts: &mut OngoingBroadcasts)
+{
+    match process.get_behaviour()
+    {
+        Behaviour::Correct | Behaviour::Equivocate =>
+        {
+            messaging::deal_with_message(process, signed_message, ongoing_broadcasts);
+        }
+        Behaviour::Silent | Behaviour::CrashAfter(0) =>
+        {
+            log!("Byzantine node ignoring message{}", signed_message);
+        }
+        Behaviour::CrashAfter(k) =>
+        {
+            process.set_behaviour(Behaviour::CrashAfter(k - 1));
+            if k == 1
+            {
+                log!("Byzantine node crashing after this message");
+            }
+            messaging::deal_with_message(process, signed_message, ongoing_broadcasts);
+        }
+        Behaviour::ForgeEcho =>
+        {
+            if signed_message.message.message_type != MessageType::Init
+            {
+                messaging::deal_with_message(process, signed_message, ongoing_broadcasts);
+                return;
+            }
+
+            let mut echo_msg = signed_message.message;
+            echo_msg.transaction = forge(&echo_msg.transaction);
+            echo_msg.sender_id = process.id;
+            echo_msg.message_type = MessageType::Echo;
+            log!("Byzantine node echoing forged transaction {}", echo_msg.transaction);
+            messaging::broadcast(process.get_transport(), echo_msg.sign(process.get_key_pair()));
+        }
+        Behaviour::EagerReady =>
+        {
+            let message = signed_message.message.clone();
+            messaging::deal_with_message(process, signed_message, ongoing_broadcasts);
+            if message.message_type != MessageType::Init
+            {
+                return;
+            }
+
+            let mut ready_msg = message;
+            ready_msg.sender_id = process.id;
+            ready_msg.message_type = MessageType::Ready;
+            log!("Byzantine node sending a ready message for {} without quorum", ready_msg.transaction);
+            messaging::broadcast(process.get_transport(), ready_msg.sign(process.get_key_pair()));
+        }
+    }
+}
+
+/// Deals with a transfer asked by a client according to the behaviour of the node
+pub fn transfer(process: &mut Process, transaction: Transaction, signature: Vec<u8>) -> (bool, u8)
+{
+    match process.get_behaviour()
+    {
+        Behaviour::Silent | Behaviour::CrashAfter(0) =>
+        {
+            // Pretend to accept the transfer, but never broadcast it
+            log!("Byzantine node ignoring transaction {}", transaction);
+            (true, 0)
+        }
+        Behaviour::Equivocate =>
+        {
+            let second = match process.init_message(transaction, signature)
+            {
+                Ok(message) => { message }
+                Err(code) => { return (false, code) }
+            };
+
+            // Pretend to accept the transfer, but keep it until the client signs a conflicting one
+            let account = second.transaction.sender_id;
+            let first = match process.get_held_init_messages().remove(&account)
+            {
+                Some(first) if first.transaction.seq_id == second.transaction.seq_id && first.transaction != second.transaction =>
+                {
+                    first
+                }
+                _ =>
+                {
+                    log!("Byzantine node keeping transaction {} until its client signs a conflicting one", second.transaction);
+                    process.get_held_init_messages().insert(account, second);
+                    return (true, 0);
+                }
+            };
+
+            // Half of the servers receive the first transaction of the client, the others the second one
+            for peer in process.get_transport().peers()
+            {
+                let init_msg: Message = match peer % 2
+                {
+                    1 => { second.clone() }
+                    _ => { first.clone() }
+                };
+                log!("Byzantine node sending Init of {} to server {}", init_msg.transaction, peer);
+                process.get_transport().send(peer, init_msg.sign(process.get_key_pair()));
+            }
+            (true, 0)
+        }
+        _ => { process.transfer(transaction, signature) }
+    }
+}
+
+/// Returns a transaction which differs from the given one: the money goes back to the sender
+fn forge(transaction: &Transaction) -> Transaction
+{
+    Transaction::from(transaction.seq_id, transaction.sender_id, transaction.sender_id, transaction.amount)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::sync::mpsc::Receiver;
+    use crate::broadcast::BroadcastKind;
+    use crate::crypto::sign_transaction;
+    use crate::event::Event;
+    use crate::test_utils;
+
+    /// Returns the transactions of the Init messages received on an event channel
+    fn received(inbox : &Receiver<Event>) -> Vec<Transaction>
+    {
+        inbox.try_iter()
+            .filter_map(|event| match event
+            {
+                Event::Message(signed_message) if signed_message.message.message_type == MessageType::Init =>
+                {
+                    Some(signed_message.message.transaction)
+                }
+                _ => { None }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn equivocating_nodes_send_conflicting_signed_transactions_to_different_servers()
+    {
+        let mut network = test_utils::network("equivocation", 4, BroadcastKind::Reliable);
+        let (account, keypair) = test_utils::owner(1);
+        let (receiver, _) = test_utils::owner(2);
+        test_utils::fund(&network, account, 100);
+        network[0].0.set_behaviour(Behaviour::Equivocate);
+
+        // The first transaction is kept until the client signs a conflicting one
+        let first = Transaction::from(1, account, receiver, 10);
+        assert_eq!(transfer(&mut network[0].0, first, sign_transaction(&first, &keypair)), (true, 0));
+        assert!(network.iter().all(|(_, inbox)| received(inbox).is_empty()));
+
+        // Transactions which are not signed by the owner of the account are still refused
+        let second = Transaction::from(1, account, receiver, 20);
+        assert_eq!(transfer(&mut network[0].0, second, sign_transaction(&second, &test_utils::owner(3).1)), (false, 1));
+        assert_eq!(transfer(&mut network[0].0, second, sign_transaction(&second, &keypair)), (true, 0));
+        let received : Vec<Vec<Transaction>> = network.iter().map(|(_, inbox)| received(inbox)).collect();
+        assert_eq!(received, vec![vec![second], vec![first], vec![second], vec![first]]);
+    }
+}
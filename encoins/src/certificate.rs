@@ -0,0 +1,164 @@
+//! Delivery certificates
+//!
+//! With the reliable broadcast, a transaction is delivered once a quorum of servers sent a Ready message
+//! for it. With the consistent one, it is delivered once its origin gathered the Echo messages of a
+//! quorum of servers. Since every message is signed, the signatures of a quorum of them prove that the
+//! transaction was delivered: a [`DeliveryCertificate`] gathers them so that anyone knowing the public
+//! keys of the servers can check the finality of a transaction without asking the servers again.
+//!
+//! Servers sign the digest of the dependencies of a message instead of the dependencies, so a certificate
+//! only carries the digest. With the dispersal broadcast, they sign the Merkle root of the fragments of
+//! the dependencies, which the certificate carries too. A certificate thus takes about 210 bytes plus
+//! 76 bytes per signer once encoded, whatever the number of dependencies of the transaction.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use ed25519_dalek::PublicKey;
+use encoins_api::base_types::Transaction;
+use serde::{Serialize, Deserialize};
+use crate::broadcast::{Weight, Weights};
+use crate::crypto::SignedMessage;
+use crate::dispersal::Digest;
+use crate::message::{Message, MessageType};
+use crate::process::ProcId;
+
+/// Signatures of the Ready or Echo messages sent by a quorum of servers for a transaction
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeliveryCertificate
+{
+    /// Delivered transaction
+    pub transaction: Transaction,
+    /// Id of the process which started the broadcast of the transaction
+    pub origin: ProcId,
+    /// Digest of the dependencies of the transaction, see [`Message::dependencies_digest`]
+    pub dependencies_digest: Digest,
+    /// Signature of the transaction by the owner of the sending account
+    pub transfer_signature: Vec<u8>,
+    /// Ids of the servers which sent a message for the transaction, with the signature of their message
+    pub signatures: Vec<(ProcId, Vec<u8>)>,
+    /// Type of the signed messages, Ready for the reliable broadcast and Echo for the consistent one
+    pub message_type: MessageType,
+    /// Merkle root of the fragments of the dependencies, set by the dispersal broadcast
+    pub dependencies_root: Option<Digest>,
+}
+
+impl DeliveryCertificate
+{
+    /// Creates the certificate of a delivered message from the signatures of the messages of the given
+    /// type sent for it
+    pub fn from(message: &Message, message_type: MessageType, signatures: Vec<(ProcId, Vec<u8>)>) -> DeliveryCertificate
+    {
+        DeliveryCertificate
+        {
+            transaction: message.transaction,
+            origin: message.origin,
+            dependencies_digest: message.dependencies_digest(),
+            transfer_signature: message.transfer_signature.clone(),
+            signatures,
+            message_type,
+            dependencies_root: message.dependencies_root,
+        }
+    }
+
+    /// Checks that distinct servers weighing at least the quorum of the type of the messages signed one
+    /// for the transaction, the i-th public key being the one of server i+1
+    pub fn verify(&self, public_keys: &[PublicKey], weights: &Weights) -> bool
+    {
+        let mut signers = HashSet::new();
+        for (id, signature) in &self.signatures
+        {
+            let public_key = match (*id as usize).checked_sub(1).and_then(|index| public_keys.get(index))
+            {
+                Some(public_key) => { public_key }
+                None => { return false }
+            };
+
+            let signed_message = SignedMessage
+            {
+                message: self.signed_message(*id),
+                signature: signature.clone(),
+            };
+            if signed_message.verif_sig_with_digest(public_key, &self.dependencies_digest).is_err()
+            {
+                return false;
+            }
+            signers.insert(*id);
+        }
+
+        let signers_weight: Weight = signers.iter().map(|id| weights.of(*id)).sum();
+        match self.message_type
+        {
+            MessageType::Ready => { signers_weight >= weights.delivery_quorum() }
+            MessageType::Echo => { signers_weight >= weights.echo_quorum() }
+            _ => { false }
+        }
+    }
+
+    /// Returns the message server `id` signed for the transaction, without its dependencies whose digest
+    /// is the one of the certificate
+    fn signed_message(&self, id: ProcId) -> Message
+    {
+        let mut message = Message::new(self.transaction, vec![], self.message_type, id, self.origin,
+            self.transfer_signature.clone());
+        message.dependencies_root = self.dependencies_root;
+        message
+    }
+}
+
+impl Display for DeliveryCertificate
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        let signers: Vec<String> = self.signatures.iter().map(|(id, _)| id.to_string()).collect();
+        write!(f, "{} delivered by servers {}", self.transaction, signers.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use ed25519_dalek::Keypair;
+    use encoins_api::base_types::UserId;
+    use rand::SeedableRng;
+    use crate::test_utils;
+    use rand::rngs::StdRng;
+
+    /// Returns the Ready message server `sender_id` sends for a transaction with the given dependencies
+    fn ready_message(sender_id : ProcId, dependencies : Vec<Transaction>) -> Message
+    {
+        let mut message = test_utils::message(MessageType::Ready, sender_id, 1, 10);
+        message.dependencies = dependencies;
+        message
+    }
+
+    #[test]
+    fn certificates_do_not_carry_the_dependencies()
+    {
+        let mut rng = StdRng::seed_from_u64(0);
+        let keypairs : Vec<Keypair> = (0..4).map(|_| Keypair::generate(&mut rng)).collect();
+        let public_keys : Vec<PublicKey> = keypairs.iter().map(|keypair| keypair.public).collect();
+        let weights = Weights::equal(4);
+        let account = UserId { id : public_keys[0] };
+
+        let mut sizes = vec![];
+        for nb_dependencies in [0, 100]
+        {
+            let dependencies = vec![Transaction::from(1, account, account, 5); nb_dependencies];
+            let signatures : Vec<(ProcId, Vec<u8>)> = (1..=3)
+                .map(|id| (id, ready_message(id, dependencies.clone()).sign(&keypairs[id as usize - 1]).signature))
+                .collect();
+            let certificate = DeliveryCertificate::from(&ready_message(1, dependencies), MessageType::Ready, signatures);
+            assert!(certificate.verify(&public_keys, &weights));
+
+            // The signatures only hold for the digest of the dependencies they were made for
+            let mut forged = certificate.clone();
+            forged.dependencies_digest = ready_message(1, vec![]).dependencies_digest();
+            assert_eq!(forged.verify(&public_keys, &weights), nb_dependencies == 0);
+
+            sizes.push(bincode::serialized_size(&certificate).unwrap());
+        }
+        assert_eq!(sizes[0], sizes[1]);
+        assert!(sizes[0] <= 210 + 3 * 76);
+    }
+}
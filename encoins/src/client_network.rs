@@ -1,15 +1,15 @@
 use std::net::{TcpListener, TcpStream};
-use std::sync::mpsc::Sender;
-use std::io::{Read, Write};
+use std::sync::mpsc::{Receiver, Sender};
 use std::sync::mpsc;
 use std::thread;
-use bincode::deserialize;
-use crate::instructions::RespInstruction;
-use encoins_api::instruction::Instruction;
+use crate::errors::decode;
+use crate::instructions::{ClientRequest, ClientResponse, RespInstruction};
+use crate::framing::{FrameDecoder, write_frame};
 use crate::log;
+use crate::event::Event;
 
 /// Manages the stream received with by socket
-pub fn client_listener(socket : (String, u16), iosender : Sender<RespInstruction>) {
+pub fn client_listener(socket : (String, u16), iosender : Sender<Event>) {
 
     let listener = TcpListener::bind(socket)
         .expect("Problem with the binding to the client socket");
@@ -31,7 +31,7 @@ pub fn client_listener(socket : (String, u16), iosender : Sender<RespInstruction
                 //handling the stream in a new thread
                 log!("New client {}", address);
                 let iosender_copy = iosender.clone();
-                thread::spawn( move || {handle_client(stream, &*address, iosender_copy);});
+                thread::spawn( move || {handle_client(stream, &address, iosender_copy);});
             }
             Err(e) =>
             {
@@ -41,52 +41,76 @@ pub fn client_listener(socket : (String, u16), iosender : Sender<RespInstruction
     }
 }
 
-/// Retransmits the content of stream with sender
-fn handle_client(mut stream: TcpStream, address: &str, sender: Sender<RespInstruction>)
+/// Retransmits the requests read on the stream with sender.
+/// Responses are written back by a dedicated thread as soon as they are available, so that a client
+/// can send several requests without waiting for the previous responses
+fn handle_client(mut stream: TcpStream, address: &str, sender: Sender<Event>)
 {
     let (resp_sender,resp_receiver) = mpsc::channel();
 
-    loop
+    let writer_stream = match stream.try_clone()
     {
-        let buf = &mut [0; 200];
+        Ok(writer_stream) => { writer_stream }
+        Err(e) =>
+        {
+            log!("Could not open the writing side of the stream of client {} : {}", address, e);
+            return;
+        }
+    };
+    let writer_address = address.to_owned();
+    thread::spawn( move || {write_responses(writer_stream, &writer_address, resp_receiver);});
 
-        match stream.read(buf)
+    let mut decoder = FrameDecoder::new();
+    loop
+    {
+        match decoder.read_frame(&mut stream)
         {
-            Ok(received) =>
+            Ok(Some(frame)) =>
             {
-                if received < 1
+                let request : ClientRequest = match decode(&frame[..])
                 {
-                    log!("Client disconnected {}", address);
-                    return;
-                }
-
-                log!("buff from client {:?}", buf);
-
-                let instruction : Instruction = deserialize(&buf[..])
-                    .expect("Problem with the deserialization of a client message");
+                    Ok(request) => { request }
+                    Err(e) =>
+                    {
+                        log!("Could not deserialize a request from client {} : {}", address, e);
+                        continue;
+                    }
+                };
 
-                log!("Instruction : {}",instruction);
+                log!("Instruction {} : {}", request.request_id, request.request);
 
                 //send instruction with sender
                 let resp_sender_copy = resp_sender.clone();
-                let resp_instruction = RespInstruction::from(instruction,resp_sender_copy);
-                sender.send(resp_instruction)
+                let resp_instruction = RespInstruction::from(request,resp_sender_copy);
+                sender.send(Event::Instruction(resp_instruction))
                     .expect("the channel between the main thread and the client thread is closed");
-
-                //write the response
-                let response = resp_receiver.recv()
-                    .expect("Problem with the reception of the response");
-                let serialized_response = &(bincode::serialize(&response)
-                    .expect("Problem with the deserialization of the response")[..]);
-                stream.write(serialized_response)
-                    .expect("Failed to write on the stream between server and client");
             }
-            Err(_) =>
+            Ok(None) =>
             {
                 log!("Client disconnected {}", address);
                 return;
             }
+            Err(e) =>
+            {
+                log!("Client disconnected {} : {}", address, e);
+                return;
+            }
         }
     }
 }
 
+/// Writes on the stream the responses to the requests of a client, in the order they are produced.
+/// Stops when the client is disconnected and every pending request has been answered
+fn write_responses(mut stream: TcpStream, address: &str, resp_receiver: Receiver<ClientResponse>)
+{
+    for response in resp_receiver
+    {
+        let serialized_response = &(bincode::serialize(&response)
+            .expect("Problem with the serialization of the response")[..]);
+        if let Err(e) = write_frame(&mut stream, serialized_response)
+        {
+            log!("Failed to write the response to request {} of client {} : {}", response.request_id, address, e);
+            return;
+        }
+    }
+}
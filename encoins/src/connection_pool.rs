@@ -0,0 +1,623 @@
+//! Long-lived outbound connections to the other servers
+//!
+//! Each server of the network gets a dedicated thread owning a [`TcpStream`] to it. Messages are
+//! handed to this thread through a channel and written on the same connection, which is opened
+//! lazily and opened again whenever it fails.
+//!
+//! Messages are kept in an outbound queue until the receiving server acknowledges them: after each
+//! message, the receiver answers with the number of messages it received on the connection. When
+//! the connection is lost, every unacknowledged message is sent again on the next one, and connection
+//! attempts to an unreachable server are spaced with an exponential backoff. The messages of a broadcast
+//! are dropped from the queue once the broadcast is forgotten (see [`ConnectionPool::forget`]), since
+//! they can not help anymore, and the queue is bounded both in number of messages and in bytes.
+//!
+//! Connections are encrypted and authenticated with [`crate::secure_channel`].
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Weak};
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::broadcast::BroadcastId;
+use crate::errors::decode;
+use crate::crypto::SignedMessage;
+use crate::log;
+use crate::process::ProcId;
+use crate::secure_channel;
+use crate::secure_channel::{ChannelKeys, SecureReader, SecureWriter};
+
+/// Delay before the first new connection attempt to an unreachable server
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+/// Maximal delay between two connection attempts to an unreachable server
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// Maximal number of unacknowledged messages kept for a server. Older messages are dropped first
+const MAX_QUEUE_LEN: usize = 100_000;
+/// Maximal size of the unacknowledged messages kept for a server (64 MiB). Older messages are dropped first
+const MAX_QUEUE_BYTES: usize = 64 * 1024 * 1024;
+/// Time allowed to a connection attempt, after which the server is deemed unreachable
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Set of outbound connections, one per server of the network
+#[derive(Debug)]
+pub struct ConnectionPool
+{
+    /// Channels to the threads managing the connection to each server. The threads only keep weak
+    /// handles on them, so that they stop once the pool is dropped
+    peers: Vec<Arc<Sender<LinkEvent>>>,
+}
+
+/// Events handled by the thread managing the connection to a server
+#[derive(Debug)]
+enum LinkEvent
+{
+    /// A message has to be sent to the server
+    Send(Arc<SignedMessage>),
+    /// The messages of the given broadcast do not have to be sent anymore
+    Forget(BroadcastId),
+    /// The server acknowledged the given number of messages on the given connection
+    Acked(u64, u64),
+    /// The given connection was closed
+    Closed(u64),
+}
+
+impl ConnectionPool
+{
+    /// Creates a pool with one connection per given address and starts their threads.
+    /// The i-th address is the one of server i+1
+    pub fn new(serv_addr: &[(String, u16)], keys: Arc<ChannelKeys>) -> ConnectionPool
+    {
+        let mut peers = Vec::new();
+        for (index, addr) in serv_addr.iter().enumerate()
+        {
+            let (sender, receiver) = mpsc::channel();
+            let sender = Arc::new(sender);
+            let link = PeerLink::new(index as ProcId + 1, addr.clone(), keys.clone(), Arc::downgrade(&sender));
+            thread::spawn(move || { link.run(receiver); });
+            peers.push(sender);
+        }
+
+        ConnectionPool
+        {
+            peers,
+        }
+    }
+
+    /// Sends a message to server `to`
+    pub fn send(&self, to: ProcId, message: SignedMessage)
+    {
+        match (to as usize).checked_sub(1).and_then(|index| self.peers.get(index))
+        {
+            Some(peer) =>
+            {
+                peer.send(LinkEvent::Send(Arc::new(message)))
+                    .expect("The thread managing the connection to a server has stopped");
+            }
+            None => { log!("There is no server with id {}", to); }
+        }
+    }
+
+    /// Sends a message to every server of the pool
+    pub fn broadcast(&self, message: SignedMessage)
+    {
+        // The links share the message
+        let message = Arc::new(message);
+        for peer in &self.peers
+        {
+            peer.send(LinkEvent::Send(message.clone()))
+                .expect("The thread managing the connection to a server has stopped");
+        }
+    }
+
+    /// Drops the messages of the given broadcast which are not acknowledged yet
+    pub fn forget(&self, broadcast_id: BroadcastId)
+    {
+        for peer in &self.peers
+        {
+            peer.send(LinkEvent::Forget(broadcast_id))
+                .expect("The thread managing the connection to a server has stopped");
+        }
+    }
+}
+
+/// A message of the outbound queue of a server
+struct QueuedMessage
+{
+    /// Broadcast the message belongs to
+    broadcast_id: BroadcastId,
+    /// Size of the serialized message
+    size: usize,
+    message: Arc<SignedMessage>,
+    /// Whether the broadcast of the message was forgotten once the message was written on the current
+    /// connection. The message is then dropped instead of being written on the next one
+    forgotten: bool,
+}
+
+/// State of the connection to one server
+struct PeerLink
+{
+    /// Id of the server
+    peer_id: ProcId,
+    /// Address of the server
+    addr: (String, u16),
+    /// Keys used to authenticate the connections
+    keys: Arc<ChannelKeys>,
+    /// Channel used by the threads reading acknowledgements to report to the link, as long as the pool exists
+    events: Weak<Sender<LinkEvent>>,
+    /// Messages not yet acknowledged by the server, oldest first
+    queue: VecDeque<QueuedMessage>,
+    /// Total size of the messages of the queue
+    queue_size: usize,
+    /// Current connection to the server, if any
+    stream: Option<SecureWriter>,
+    /// Id of the current connection, used to ignore events of previous connections
+    connection_id: u64,
+    /// Number of messages of the queue already written on the current connection
+    written: usize,
+    /// Number of messages acknowledged by the server on the current connection
+    acked: u64,
+    /// Delay to wait after the next failed connection attempt
+    backoff: Duration,
+    /// Instant of the next connection attempt
+    next_attempt: Instant,
+}
+
+impl PeerLink
+{
+    fn new(peer_id: ProcId, addr: (String, u16), keys: Arc<ChannelKeys>, events: Weak<Sender<LinkEvent>>) -> PeerLink
+    {
+        PeerLink
+        {
+            peer_id,
+            addr,
+            keys,
+            events,
+            queue: VecDeque::new(),
+            queue_size: 0,
+            stream: None,
+            connection_id: 0,
+            written: 0,
+            acked: 0,
+            backoff: MIN_BACKOFF,
+            next_attempt: Instant::now(),
+        }
+    }
+
+    /// Handles the events of the link until the pool is dropped, then closes the connection
+    fn run(mut self, receiver: Receiver<LinkEvent>)
+    {
+        loop
+        {
+            let event = if self.stream.is_none() && !self.queue.is_empty()
+            {
+                // Wake up in time for the next connection attempt
+                match receiver.recv_timeout(self.next_attempt.saturating_duration_since(Instant::now()))
+                {
+                    Ok(event) => { Some(event) }
+                    Err(RecvTimeoutError::Timeout) => { None }
+                    Err(RecvTimeoutError::Disconnected) => { break; }
+                }
+            }
+            else
+            {
+                match receiver.recv()
+                {
+                    Ok(event) => { Some(event) }
+                    Err(_) => { break; }
+                }
+            };
+
+            match event
+            {
+                Some(LinkEvent::Send(message)) => { self.enqueue(message); }
+                Some(LinkEvent::Forget(broadcast_id)) => { self.forget(&broadcast_id); }
+                // Events of a previous connection are ignored
+                Some(LinkEvent::Acked(connection_id, count)) if connection_id == self.connection_id =>
+                {
+                    self.acknowledge(count);
+                }
+                Some(LinkEvent::Closed(connection_id)) if connection_id == self.connection_id && self.stream.is_some() =>
+                {
+                    log!("Connexion to server {:?} lost", self.addr);
+                    self.disconnect();
+                }
+                Some(LinkEvent::Acked(..)) | Some(LinkEvent::Closed(_)) | None => {}
+            }
+
+            self.flush();
+        }
+
+        if let Some(stream) = self.stream.take()
+        {
+            stream.shutdown();
+        }
+    }
+
+    /// Adds a message to the queue, dropping the oldest ones if the queue is full
+    fn enqueue(&mut self, message: Arc<SignedMessage>)
+    {
+        let size = bincode::serialized_size(&*message).map_or(usize::MAX, |size| size as usize);
+        self.queue_size = self.queue_size.saturating_add(size);
+        self.queue.push_back(QueuedMessage { broadcast_id: BroadcastId::of(&message.message), size, message, forgotten: false });
+
+        while self.queue.len() > MAX_QUEUE_LEN || self.queue_size > MAX_QUEUE_BYTES
+        {
+            match self.pop_front()
+            {
+                Some(dropped) =>
+                {
+                    log!("Outbound queue to server {:?} is full, dropping message {}", self.addr, dropped.message);
+                }
+                None => { break; }
+            }
+            // The dropped message was the first one written on the connection, if any
+            if self.written > 0
+            {
+                self.written -= 1;
+                self.acked += 1;
+            }
+        }
+    }
+
+    /// Removes and returns the oldest message of the queue
+    fn pop_front(&mut self) -> Option<QueuedMessage>
+    {
+        let queued = self.queue.pop_front()?;
+        self.queue_size = self.queue_size.saturating_sub(queued.size);
+        Some(queued)
+    }
+
+    /// Removes the messages of the queue which satisfy the predicate, from index `from` on
+    fn remove_from<F>(&mut self, from: usize, predicate: F) where F: Fn(&QueuedMessage) -> bool
+    {
+        for queued in self.queue.split_off(from)
+        {
+            match predicate(&queued)
+            {
+                true => { self.queue_size = self.queue_size.saturating_sub(queued.size); }
+                false => { self.queue.push_back(queued); }
+            }
+        }
+    }
+
+    /// Drops the messages of a broadcast. The ones written on the current connection stay in the queue
+    /// until they are acknowledged, so that the acknowledgements still match the queue, but they will
+    /// not be written again
+    fn forget(&mut self, broadcast_id: &BroadcastId)
+    {
+        for queued in self.queue.iter_mut().take(self.written)
+        {
+            queued.forgotten |= queued.broadcast_id == *broadcast_id;
+        }
+        self.remove_from(self.written, |queued| queued.broadcast_id == *broadcast_id);
+    }
+
+    /// Removes from the queue the messages the server acknowledged
+    fn acknowledge(&mut self, count: u64)
+    {
+        while self.acked < count && self.written > 0
+        {
+            self.pop_front();
+            self.written -= 1;
+            self.acked += 1;
+        }
+        // The server is reachable again
+        self.backoff = MIN_BACKOFF;
+    }
+
+    /// Forgets the current connection. Its unacknowledged messages will be written on the next one,
+    /// except the ones of forgotten broadcasts
+    fn disconnect(&mut self)
+    {
+        if let Some(stream) = self.stream.take()
+        {
+            // Also stops the thread reading the acknowledgements
+            stream.shutdown();
+        }
+        self.written = 0;
+        self.remove_from(0, |queued| queued.forgotten);
+        self.next_attempt = Instant::now() + self.backoff;
+        self.backoff = std::cmp::min(self.backoff * 2, MAX_BACKOFF);
+    }
+
+    /// Writes the messages of the queue which have not been written on the current connection yet,
+    /// connecting to the server first if needed and allowed by the backoff
+    fn flush(&mut self)
+    {
+        if self.queue.len() == self.written
+        {
+            return;
+        }
+
+        if self.stream.is_none()
+        {
+            if Instant::now() < self.next_attempt
+            {
+                return;
+            }
+            match self.connect()
+            {
+                Some(stream) => { self.stream = Some(stream); }
+                None =>
+                {
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+
+        while self.written < self.queue.len()
+        {
+            let serialized_msg = &(bincode::serialize(&*self.queue[self.written].message)
+                .expect("Problem with the serialization of a message before sending phase")[..]);
+            let result = match self.stream.as_mut()
+            {
+                Some(stream) => { stream.send(serialized_msg) }
+                None => { return; }
+            };
+            match result
+            {
+                Ok(_) => { self.written += 1; }
+                Err(e) =>
+                {
+                    log!("Connexion to server {:?} lost : {}", self.addr, e);
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Opens a TCP connection to the server, trying each of its addresses for at most [`CONNECT_TIMEOUT`]
+    fn open(&self) -> Result<TcpStream, Error>
+    {
+        let mut last_error = Error::new(ErrorKind::NotFound, "the address of the server does not resolve");
+        for addr in (self.addr.0.as_str(), self.addr.1).to_socket_addrs()?
+        {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+            {
+                Ok(stream) => { return Ok(stream); }
+                Err(e) => { last_error = e; }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Opens a new connection to the server and starts the thread reading its acknowledgements
+    fn connect(&mut self) -> Option<SecureWriter>
+    {
+        let stream = match self.open()
+        {
+            Ok(stream) => { stream }
+            Err(e) =>
+            {
+                log!("Connexion to server {:?} failed : {} (next attempt in {:?})", self.addr, e, self.backoff);
+                return None;
+            }
+        };
+
+        if let Err(e) = stream.set_nodelay(true)
+        {
+            log!("Could not disable Nagle's algorithm on the connection to {:?} : {}", self.addr, e);
+        }
+
+        let (writer, reader) = match secure_channel::connect(stream, &self.keys, self.peer_id)
+        {
+            Ok(channel) => { channel }
+            Err(e) =>
+            {
+                log!("Could not establish a secure channel with server {} : {}", self.peer_id, e);
+                return None;
+            }
+        };
+
+        self.connection_id += 1;
+        self.acked = 0;
+        let connection_id = self.connection_id;
+        let events = self.events.clone();
+        thread::spawn(move || { read_acks(reader, connection_id, events); });
+
+        log!("Connected to server {:?}, {} message(s) to send", self.addr, self.queue.len());
+        Some(writer)
+    }
+}
+
+/// Reports to the link the acknowledgements sent by the server on a connection, then its closing.
+/// Stops as soon as the pool is dropped
+fn read_acks(mut reader: SecureReader, connection_id: u64, events: Weak<Sender<LinkEvent>>)
+{
+    let report = |event : LinkEvent| match events.upgrade()
+    {
+        Some(events) => { events.send(event).is_ok() }
+        None => { false }
+    };
+    while let Ok(Some(frame)) = reader.receive()
+    {
+        let count : u64 = match decode(&frame[..])
+        {
+            Ok(count) => { count }
+            Err(_) => { break; }
+        };
+        if !report(LinkEvent::Acked(connection_id, count))
+        {
+            return;
+        }
+    }
+    report(LinkEvent::Closed(connection_id));
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use ed25519_dalek::Keypair;
+    use encoins_api::base_types::{SeqId, Transaction};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::event::Event;
+    use crate::message::MessageType;
+    use crate::serv_network::server_listener;
+    use crate::test_utils;
+
+    /// Returns the keys of a server 1 which only knows itself
+    fn keys() -> Arc<ChannelKeys>
+    {
+        let keypair = Keypair::generate(&mut StdRng::seed_from_u64(0));
+        let public_key = keypair.public;
+        Arc::new(ChannelKeys::new(1, keypair, HashMap::from([(1, public_key)])))
+    }
+
+    /// Returns a local address on which no server listens
+    fn closed_address() -> (String, u16)
+    {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).expect("Impossible to bind a local port");
+        (String::from("127.0.0.1"), listener.local_addr().expect("A bound listener has an address").port())
+    }
+
+    /// Returns a link to a server which does not listen, with the channel of its events
+    fn unreachable_link() -> (PeerLink, Arc<Sender<LinkEvent>>, Receiver<LinkEvent>)
+    {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Arc::new(sender);
+        (PeerLink::new(1, closed_address(), keys(), Arc::downgrade(&sender)), sender, receiver)
+    }
+
+    /// Returns the Echo message of server 1 for transaction `seq_id` of the test account
+    fn echo_message(seq_id : SeqId) -> SignedMessage
+    {
+        SignedMessage { message : test_utils::message(MessageType::Echo, 1, seq_id, 10), signature : vec![] }
+    }
+
+    /// Returns the sequence numbers of the transactions of the messages of the queue of a link
+    fn queued(link : &PeerLink) -> Vec<SeqId>
+    {
+        link.queue.iter().map(|queued| queued.message.message.transaction.seq_id).collect()
+    }
+
+    #[test]
+    fn forgotten_broadcasts_leave_the_queue()
+    {
+        test_utils::initialize_logs();
+        let (mut link, _sender, _) = unreachable_link();
+        for seq_id in [1, 2, 1, 2, 3]
+        {
+            link.enqueue(Arc::new(echo_message(seq_id)));
+        }
+        let size = link.queue_size;
+
+        // The messages written on the connection wait for their acknowledgement, but are not written again
+        link.written = 2;
+        link.forget(&BroadcastId::of(&echo_message(1).message));
+        assert_eq!(queued(&link), vec![1, 2, 2, 3]);
+        assert_eq!(link.queue_size, size / 5 * 4);
+        link.disconnect();
+        assert_eq!(queued(&link), vec![2, 2, 3]);
+        assert_eq!(link.queue_size, size / 5 * 3);
+
+        link.written = 2;
+        link.acknowledge(1);
+        assert_eq!(queued(&link), vec![2, 3]);
+        assert_eq!((link.written, link.acked), (1, 1));
+    }
+
+    #[test]
+    fn queue_is_bounded_in_messages_and_bytes()
+    {
+        test_utils::initialize_logs();
+        let (mut link, _sender, _) = unreachable_link();
+        let mut message = echo_message(0);
+        for seq_id in 0..=MAX_QUEUE_LEN as SeqId
+        {
+            message.message.transaction.seq_id = seq_id;
+            link.enqueue(Arc::new(message.clone()));
+        }
+        assert_eq!(link.queue.len(), MAX_QUEUE_LEN);
+        assert_eq!(queued(&link)[0], 1);
+
+        let (mut link, _sender, _) = unreachable_link();
+        let account = test_utils::account();
+        let mut large = echo_message(0);
+        large.message.dependencies = vec![Transaction::from(1, account, account, 5); 10_000];
+        let size = bincode::serialized_size(&large).unwrap() as usize;
+        for seq_id in 0..=(MAX_QUEUE_BYTES / size) as SeqId
+        {
+            large.message.transaction.seq_id = seq_id;
+            link.enqueue(Arc::new(large.clone()));
+        }
+        assert_eq!(link.queue.len(), MAX_QUEUE_BYTES / size);
+        assert_eq!(queued(&link)[0], 1);
+        assert!(link.queue_size <= MAX_QUEUE_BYTES);
+    }
+
+    #[test]
+    fn unreachable_servers_are_retried_with_an_exponential_backoff()
+    {
+        test_utils::initialize_logs();
+        let (mut link, _sender, _) = unreachable_link();
+        link.enqueue(Arc::new(echo_message(1)));
+
+        link.flush();
+        assert_eq!(link.backoff, MIN_BACKOFF * 2);
+        assert!(link.stream.is_none() && link.next_attempt > Instant::now());
+        // No new attempt before the end of the backoff
+        link.flush();
+        assert_eq!(link.backoff, MIN_BACKOFF * 2);
+
+        for _ in 0..10
+        {
+            link.next_attempt = Instant::now();
+            link.flush();
+        }
+        assert_eq!(link.backoff, MAX_BACKOFF);
+
+        // An acknowledgement shows the server is reachable again
+        link.acknowledge(0);
+        assert_eq!(link.backoff, MIN_BACKOFF);
+        assert_eq!(queued(&link), vec![1]);
+    }
+
+    #[test]
+    fn links_stop_with_their_pool()
+    {
+        test_utils::initialize_logs();
+        let (link, events, receiver) = unreachable_link();
+        let (stopped, stop) = mpsc::channel();
+        thread::spawn(move ||
+        {
+            link.run(receiver);
+            let _ = stopped.send(());
+        });
+
+        // The link keeps trying to connect while it has messages to send, until the pool is dropped
+        events.send(LinkEvent::Send(Arc::new(echo_message(1)))).unwrap();
+        drop(events);
+        assert!(stop.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    #[test]
+    fn messages_are_delivered_once_the_server_is_up()
+    {
+        test_utils::initialize_logs();
+        let addr = closed_address();
+        let pool = ConnectionPool::new(std::slice::from_ref(&addr), keys());
+        pool.send(1, echo_message(1));
+        pool.send(1, echo_message(2));
+
+        // The first attempts fail, the messages are kept until the server listens
+        thread::sleep(MIN_BACKOFF);
+        let (events, inbox) = mpsc::channel();
+        thread::spawn(move || { server_listener(addr, events, keys()); });
+
+        let received : Vec<SeqId> = (0..2).map(|_| match inbox.recv_timeout(Duration::from_secs(10))
+            {
+                Ok(Event::Message(signed_message)) => { signed_message.message.transaction.seq_id }
+                _ => { panic!("The messages were not delivered") }
+            })
+            .collect();
+        assert_eq!(received, vec![1, 2]);
+    }
+}
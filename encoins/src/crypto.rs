@@ -1,10 +1,28 @@
 extern crate rand;
 extern crate ed25519_dalek;
 use serde::{Serialize,Deserialize};
-use ed25519_dalek::{PublicKey, Verifier,Signature,Keypair};
+use std::convert::TryFrom;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use ed25519_dalek::{PublicKey, Keypair};
+use ed25519_zebra::{batch, VerificationKey, VerificationKeyBytes};
 use rand::rngs::OsRng;
+use sha2::{Digest as _, Sha256};
+use zeroize::Zeroizing;
+use encoins_api::base_types::Transaction;
+use encoins_api::transfer::Transfer;
 use crate::crypto::ed25519_dalek::Signer;
-use crate::message::Message;
+use crate::dispersal::{Digest, Fragment};
+use crate::errors::DecodeError;
+use crate::instructions::{NodeResponse, SignedResponse};
+use crate::message::{Message, MessageType};
+use crate::process::ProcId;
+
+/// Context signed by the owner of an account with its transactions
+pub const TRANSACTION_CONTEXT: &[u8] = b"encoins transaction";
+/// Context signed by a node with its responses to the clients
+pub const RESPONSE_CONTEXT: &[u8] = b"encoins node response";
+/// Context signed by a server with its messages to the other servers
+pub const MESSAGE_CONTEXT: &[u8] = b"encoins server message";
 
 /// A SignedMessage is a message and its signature
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -17,14 +35,29 @@ pub struct SignedMessage
     pub signature : Vec<u8>
 }
 
+/// What a server signs for a message: the message with its dependencies replaced by their digest, so
+/// that a certificate can prove the delivery of a transaction without carrying its dependencies
+#[derive(Serialize)]
+struct SignedContent<'a>
+{
+    transaction : &'a Transaction,
+    dependencies_digest : &'a Digest,
+    message_type : MessageType,
+    sender_id : ProcId,
+    origin : ProcId,
+    transfer_signature : &'a [u8],
+    echo_signatures : &'a [(ProcId, Vec<u8>)],
+    origin_signature : &'a [u8],
+    dependencies_root : &'a Option<Digest>,
+    fragment : &'a Option<Fragment>,
+}
+
 impl Message 
 {
     /// A method that given a keypair returns the signed version of the message
     pub fn sign(self,keypair: &Keypair) -> SignedMessage
     {
-        let msg : &[u8] =  &(bincode::serialize(&self)
-            .expect("problem with the deserialization o")[..]);
-        let signature  = keypair.sign(msg).to_bytes().to_vec();
+        let signature  = keypair.sign(&self.signed_content(&self.dependencies_digest())).to_bytes().to_vec();
         SignedMessage 
         {
             message : self,
@@ -32,26 +65,225 @@ impl Message
         }
     }
 
+    /// Returns the digest of the dependencies of the message
+    pub fn dependencies_digest(&self) -> Digest
+    {
+        let serialized = bincode::serialize(&self.dependencies).expect("Problem with the serialization of dependencies");
+        Sha256::digest(&serialized).into()
+    }
+
+    /// Returns the bytes a server signs for the message, whose dependencies have the given digest. They
+    /// start with [`MESSAGE_CONTEXT`], so that a message can not be taken for anything else the server signs
+    /// with the same key
+    fn signed_content(&self, dependencies_digest : &Digest) -> Vec<u8>
+    {
+        let content = SignedContent
+        {
+            transaction : &self.transaction,
+            dependencies_digest,
+            message_type : self.message_type,
+            sender_id : self.sender_id,
+            origin : self.origin,
+            transfer_signature : &self.transfer_signature,
+            echo_signatures : &self.echo_signatures,
+            origin_signature : &self.origin_signature,
+            dependencies_root : &self.dependencies_root,
+            fragment : &self.fragment,
+        };
+        let mut signed = MESSAGE_CONTEXT.to_vec();
+        signed.extend(bincode::serialize(&content).expect("Problem with the serialization of a message"));
+        signed
+    }
+
+    /// States whether the transaction of the message was signed by the owner of the sending account
+    pub fn verif_transfer_signature(&self) -> bool
+    {
+        self.transfer_signing().is_some()
+    }
+
+    /// Returns how the owner of the sending account signed the transaction of the message, or `None` if they
+    /// did not sign it
+    pub fn transfer_signing(&self) -> Option<TransferSigning>
+    {
+        transfer_signing(&self.transaction, &self.transfer_signature)
+    }
+
+}
+
+/// Returns the bytes the owner of an account signs to spend from it. They cover the whole transaction,
+/// so that the node the client asks can not give it another sequence number
+fn signed_transaction(transaction: &Transaction) -> Vec<u8>
+{
+    let mut signed = TRANSACTION_CONTEXT.to_vec();
+    signed.extend(bincode::serialize(transaction).expect("Problem with the serialization of a transaction"));
+    signed
+}
+
+/// Signs a transaction as the client owning the sending account does
+pub fn sign_transaction(transaction: &Transaction, keypair: &Keypair) -> Vec<u8>
+{
+    keypair.sign(&signed_transaction(transaction)).to_bytes().to_vec()
+}
+
+/// States whether the transaction was signed by the owner of the sending account
+pub fn verif_transaction_signature(transaction: &Transaction, signature: &[u8]) -> bool
+{
+    verify_signature(&transaction.sender_id.id, &signed_transaction(transaction), signature).is_ok()
+}
+
+/// How the owner of an account signed a transaction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferSigning
+{
+    /// The whole transaction was signed, see [`sign_transaction`]
+    Transaction,
+    /// Only the [`Transfer`] of the encoins API was signed, without the sequence number. Such signatures
+    /// are accepted until the clients sign whole transactions, but can be replayed with another sequence
+    /// number, so an account can not validate two such transfers with the same receiver and amount
+    Legacy,
+}
+
+/// Returns how the owner of the sending account signed the transaction, or `None` if the signature is not theirs
+pub fn transfer_signing(transaction: &Transaction, signature: &[u8]) -> Option<TransferSigning>
+{
+    if verif_transaction_signature(transaction, signature)
+    {
+        return Some(TransferSigning::Transaction);
+    }
+    let transfer = Transfer
+    {
+        sender: transaction.sender_id,
+        recipient: transaction.receiver_id,
+        amount: transaction.amount,
+    };
+    let signed = bincode::serialize(&transfer).expect("Problem with the serialization of a transfer");
+    match verify_signature(&transaction.sender_id.id, &signed, signature)
+    {
+        Ok(()) => { Some(TransferSigning::Legacy) }
+        Err(_) => { None }
+    }
+}
+
+/// Checks that `signature` is a signature of `content` with the given public key.
+///
+/// Every signature, of a server or of a client, is checked with the rules of ZIP 215, which
+/// accept the same signatures whether they are checked alone or in a batch by [`verif_sigs`]. Whether
+/// a signature is accepted thus does not depend on the signatures received with it, and every correct
+/// server accepts the same ones. Since these rules accept any signature for a public key of small
+/// order, such keys are rejected beforehand
+pub fn verify_signature(public_key: &PublicKey, content: &[u8], signature: &[u8]) -> Result<(), DecodeError>
+{
+    if small_order(public_key)
+    {
+        return Err(DecodeError::InvalidSignature);
+    }
+    let signature = ed25519_zebra::Signature::try_from(signature).map_err(|_| DecodeError::MalformedSignature)?;
+    let public_key = VerificationKey::try_from(public_key.to_bytes()).map_err(|_| DecodeError::InvalidSignature)?;
+    public_key.verify(&signature, content).map_err(|_| DecodeError::InvalidSignature)
+}
+
+/// States whether a public key is a point of small order, for which anyone can forge signatures
+fn small_order(public_key: &PublicKey) -> bool
+{
+    match CompressedEdwardsY(public_key.to_bytes()).decompress()
+    {
+        Some(point) => { point.is_small_order() }
+        None => { true }
+    }
 }
 
 impl SignedMessage 
 {
     /// A method that given a public_key returns the message if the signature is right and returns an error otherwise
-    pub fn verif_sig(self, public_key: &PublicKey) -> Result<Message, String> {
+    pub fn verif_sig(self, public_key: &PublicKey) -> Result<Message, DecodeError> {
+
+        let dependencies_digest = self.message.dependencies_digest();
+        self.verif_sig_with_digest(public_key, &dependencies_digest)?;
+        Ok(self.message)
+    }
+
+    /// Checks the signature of a message whose dependencies have the given digest, whichever dependencies
+    /// the message carries
+    pub fn verif_sig_with_digest(&self, public_key: &PublicKey, dependencies_digest: &Digest) -> Result<(), DecodeError>
+    {
+        verify_signature(public_key, &self.message.signed_content(dependencies_digest), &self.signature)
+    }
+}
 
-        let message = self.message;
-        let msg = &(bincode::serialize(&message)
-            .expect("Problem with th deserialization of the message")[..]);
 
-        match public_key.verify(msg, &Signature::from_bytes(self.signature.as_slice())
-            .expect("Problem with the conversion from signature to bytes")).is_ok()
+impl NodeResponse
+{
+    /// Signs the response with the keypair of the node
+    pub fn sign(self, keypair: &Keypair) -> SignedResponse
+    {
+        let signature = keypair.sign(&self.signed_content()).to_bytes().to_vec();
+        SignedResponse
         {
-            true => { Ok(message) }
-            false => { Err(String::from("The signature is not valid!")) }
+            response: self,
+            signature
         }
     }
+
+    /// Returns the bytes a node signs for the response. They start with [`RESPONSE_CONTEXT`], so that a
+    /// response can not be taken for anything else the node signs with the same key
+    fn signed_content(&self) -> Vec<u8>
+    {
+        let mut signed = RESPONSE_CONTEXT.to_vec();
+        signed.extend(bincode::serialize(self).expect("Problem with the serialization of a response"));
+        signed
+    }
 }
 
+impl SignedResponse
+{
+    /// Returns the response if it was signed with the given public key, and an error otherwise
+    pub fn verif_sig(self, public_key: &PublicKey) -> Result<NodeResponse, DecodeError>
+    {
+        verify_signature(public_key, &self.response.signed_content(), &self.signature)?;
+        Ok(self.response)
+    }
+}
+
+/// Checks the signatures of several messages at once, the i-th message being signed with the i-th
+/// public key. The signatures are first checked together with a batch verification, which is much
+/// faster than checking them one by one. If the batch is rejected, they are checked one by one to
+/// find which ones are wrong. Both ways accept the same signatures (see [`verify_signature`])
+pub fn verif_sigs(signed_messages: &[SignedMessage], public_keys: &[&PublicKey]) -> Vec<Result<(), DecodeError>>
+{
+    let serialized: Vec<Vec<u8>> = signed_messages.iter()
+        .map(|signed_message| signed_message.message.signed_content(&signed_message.message.dependencies_digest()))
+        .collect();
+    let signatures: Vec<Result<ed25519_zebra::Signature, DecodeError>> = signed_messages.iter()
+        .map(|signed_message| ed25519_zebra::Signature::try_from(signed_message.signature.as_slice())
+            .map_err(|_| DecodeError::MalformedSignature))
+        .collect();
+
+    // Malformed signatures and keys of small order can not be part of the batch
+    let batched: Vec<bool> = (0..signed_messages.len())
+        .map(|i| signatures[i].is_ok() && !small_order(public_keys[i]))
+        .collect();
+    let mut batch = batch::Verifier::new();
+    for i in 0..signed_messages.len()
+    {
+        if let (true, Ok(signature)) = (batched[i], &signatures[i])
+        {
+            batch.queue((VerificationKeyBytes::from(public_keys[i].to_bytes()), *signature, &serialized[i]));
+        }
+    }
+    let batch_valid = batch.verify(OsRng).is_ok();
+
+    signatures.into_iter().enumerate()
+        .map(|(i, signature)|
+        {
+            signature?;
+            match batch_valid && batched[i]
+            {
+                true => { Ok(()) }
+                false => { verify_signature(public_keys[i], &serialized[i], &signed_messages[i].signature) }
+            }
+        })
+        .collect()
+}
 
 
 /// The function that returns a list of N public_keys and a list of N keypair_keys to be granted to processes
@@ -60,4 +292,85 @@ pub fn create_keypair() -> Keypair
     let mut csprng = OsRng{};
     let keypair: Keypair = Keypair::generate(&mut csprng);
     keypair
-}
\ No newline at end of file
+}
+
+/// Returns a copy of a keypair. The secret key of a keypair is zeroized when it is dropped, and so
+/// is the copy of its bytes made here
+pub fn copy_keypair(keypair: &Keypair) -> Keypair
+{
+    let bytes = Zeroizing::new(keypair.to_bytes());
+    Keypair::from_bytes(&bytes[..]).expect("Problem with the copy of a keypair")
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use encoins_api::base_types::UserId;
+    use crate::test_utils;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn batches_accept_the_signatures_accepted_one_by_one()
+    {
+        let mut rng = StdRng::seed_from_u64(0);
+        let keypairs : Vec<Keypair> = (0..4).map(|_| Keypair::generate(&mut rng)).collect();
+        // Anyone can sign for the identity point, which is of small order
+        let mut identity = [0; 32];
+        identity[0] = 1;
+        let small_order_key = PublicKey::from_bytes(&identity).unwrap();
+        let mut forged_signature = vec![0; 64];
+        forged_signature[0] = 1;
+
+        let mut signed_messages : Vec<SignedMessage> = (1..=4)
+            .map(|id| test_utils::message(MessageType::Echo, id, 1, 10).sign(&keypairs[id as usize - 1]))
+            .collect();
+        let mut public_keys : Vec<&PublicKey> = keypairs.iter().map(|keypair| &keypair.public).collect();
+        assert!(verif_sigs(&signed_messages, &public_keys).iter().all(|result| result.is_ok()));
+
+        signed_messages[1].message.transaction.amount += 1;
+        signed_messages[2].signature.truncate(10);
+        signed_messages[3].signature = forged_signature;
+        public_keys[3] = &small_order_key;
+        let one_by_one : Vec<bool> = signed_messages.iter().zip(&public_keys)
+            .map(|(signed_message, public_key)| signed_message.clone().verif_sig(public_key).is_ok())
+            .collect();
+        let batch : Vec<bool> = verif_sigs(&signed_messages, &public_keys).iter().map(|result| result.is_ok()).collect();
+        assert_eq!(one_by_one, vec![true, false, false, false]);
+        assert_eq!(batch, one_by_one);
+    }
+
+    #[test]
+    fn messages_are_signed_with_their_context()
+    {
+        let keypair = Keypair::generate(&mut StdRng::seed_from_u64(0));
+        let message = test_utils::message(MessageType::Ready, 1, 1, 10);
+        assert!(message.clone().sign(&keypair).verif_sig(&keypair.public).is_ok());
+
+        // A signature of the same bytes without the context is not a signature of the message
+        let content = message.signed_content(&message.dependencies_digest());
+        let signature = keypair.sign(&content[MESSAGE_CONTEXT.len()..]).to_bytes().to_vec();
+        assert!(SignedMessage { message, signature }.verif_sig(&keypair.public).is_err());
+    }
+
+    #[test]
+    fn responses_are_signed_with_their_context()
+    {
+        let keypair = Keypair::generate(&mut StdRng::seed_from_u64(0));
+        let response = || NodeResponse
+        {
+            node_id : 1,
+            timestamp : 0,
+            request_id : 2,
+            user : UserId { id : keypair.public },
+            response : encoins_api::response::Response::Balance(10),
+        };
+        assert!(response().sign(&keypair).verif_sig(&keypair.public).is_ok());
+
+        // A signature of the same bytes without the context is not a signature of the response
+        let serialized = bincode::serialize(&response()).unwrap();
+        let signed_response = SignedResponse { response : response(), signature : keypair.sign(&serialized).to_bytes().to_vec() };
+        assert!(signed_response.verif_sig(&keypair.public).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+//! Erasure-coded dispersal of the dependencies of a transaction (AVID, see *Asynchronous Verifiable
+//! Information Dispersal*, Cachin and Tessaro, SRDS 2005)
+//!
+//! The dependencies of a transaction grow with the number of transactions an account received, and
+//! every Echo and Ready message of a reliable broadcast repeats them. With a dispersal broadcast, the
+//! origin instead encodes them into one fragment per server with a Reed-Solomon code, so that any
+//! `f+1` fragments are enough to rebuild them, and commits to the fragments with the root of a Merkle
+//! tree. Each server only receives its own fragment, and echoes it with its Merkle proof:
+//! - Echo messages carry the fragment of their sender and the root ;
+//! - Ready messages only carry the root.
+//!
+//! A server delivers once the Ready messages of the reliable broadcast weigh enough and it received
+//! `f+1` fragments matching the root. It then rebuilds the dependencies, encodes them again and checks
+//! that they give the same root, so that a byzantine origin can not make correct servers rebuild
+//! different dependencies. A server thus sends and receives fragments of about `1/(f+1)` of the size
+//! of the dependencies, instead of the whole dependencies.
+//!
+//! The size of a fragment does not depend on the weight of a server, so this broadcast needs every
+//! server to weigh 1.
+
+use std::collections::HashMap;
+use std::time::Instant;
+use encoins_api::base_types::Transaction;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest as _, Sha256};
+use crate::broadcast::{Action, Broadcast, BroadcastInstance, init_broadcast, Weights};
+use crate::certificate::DeliveryCertificate;
+use crate::errors;
+use crate::log;
+use crate::message::{Message, MessageType};
+use crate::process::ProcId;
+
+/// Hash of a node of a Merkle tree
+pub type Digest = [u8; 32];
+
+/// Size of the header of the encoded dependencies, which holds their length
+const LENGTH_SIZE : usize = 8;
+
+/// Fragment of the encoded dependencies of a transaction, with the proof that it belongs to the Merkle
+/// tree of the fragments
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fragment
+{
+    /// Index of the fragment, which is the one of server `index + 1`
+    pub index : u32,
+    /// Content of the fragment
+    pub data : Vec<u8>,
+    /// Hashes of the siblings of the path from the fragment to the root, from the bottom
+    pub proof : Vec<Digest>,
+}
+
+/// Checks that every server weighs 1 and that there are enough servers to encode the dependencies
+pub fn check(weights : &Weights) -> Result<(), String>
+{
+    let servers = weights.servers();
+    if servers.iter().any(|id| weights.of(*id) != 1)
+    {
+        return Err(String::from("The dispersal broadcast needs every server to weigh 1"));
+    }
+    if servers.len() < 2 || servers.len() > 256
+    {
+        return Err(format!("The dispersal broadcast needs between 2 and 256 servers, not {}", servers.len()));
+    }
+    Ok(())
+}
+
+/// Returns the number of fragments needed to rebuild the dependencies
+fn nb_data_fragments(nb_servers : usize, fault_threshold : usize) -> usize
+{
+    (fault_threshold + 1).min(nb_servers - 1)
+}
+
+/// Encodes the dependencies into `nb_servers` fragments, any `f+1` of them being enough to rebuild them
+fn encode(dependencies : &[Transaction], nb_servers : usize, fault_threshold : usize) -> Vec<Vec<u8>>
+{
+    let nb_data = nb_data_fragments(nb_servers, fault_threshold);
+    let payload = bincode::serialize(dependencies).expect("Problem with the serialization of dependencies");
+
+    // The payload is prefixed with its length, then padded so that it splits into fragments of the same size
+    let fragment_size = (LENGTH_SIZE + payload.len()).div_ceil(nb_data);
+    let mut padded = (payload.len() as u64).to_le_bytes().to_vec();
+    padded.extend_from_slice(&payload);
+    padded.resize(fragment_size * nb_data, 0);
+
+    let mut fragments : Vec<Vec<u8>> = padded.chunks(fragment_size).map(|chunk| chunk.to_vec()).collect();
+    fragments.resize(nb_servers, vec![0; fragment_size]);
+    ReedSolomon::new(nb_data, nb_servers - nb_data)
+        .and_then(|codec| codec.encode(&mut fragments))
+        .expect("Problem with the encoding of dependencies");
+    fragments
+}
+
+/// Rebuilds the dependencies from at least `f+1` fragments, by index. Returns None if the fragments
+/// are not the ones of encoded dependencies
+fn decode(fragments : &HashMap<u32, &[u8]>, nb_servers : usize, fault_threshold : usize) -> Option<Vec<Transaction>>
+{
+    let nb_data = nb_data_fragments(nb_servers, fault_threshold);
+    let fragment_size = fragments.values().next()?.len();
+    if fragments.values().any(|data| data.len() != fragment_size)
+    {
+        return None;
+    }
+
+    let mut shards : Vec<Option<Vec<u8>>> = (0..nb_servers as u32).map(|index| fragments.get(&index).map(|data| data.to_vec())).collect();
+    ReedSolomon::new(nb_data, nb_servers - nb_data).ok()?.reconstruct_data(&mut shards).ok()?;
+    let padded : Vec<u8> = shards.into_iter().take(nb_data).flat_map(|shard| shard.unwrap_or_default()).collect();
+
+    let mut length = [0; LENGTH_SIZE];
+    length.copy_from_slice(padded.get(..LENGTH_SIZE)?);
+    let length = u64::from_le_bytes(length) as usize;
+    let payload = padded.get(LENGTH_SIZE..LENGTH_SIZE.checked_add(length)?)?;
+    errors::decode(payload).ok()
+}
+
+/// Returns the hash of a leaf of a Merkle tree
+fn hash_leaf(data : &[u8]) -> Digest
+{
+    Sha256::new().chain_update([0]).chain_update(data).finalize().into()
+}
+
+/// Returns the hash of an inner node of a Merkle tree, given the hashes of its children
+fn hash_node(left : &Digest, right : &Digest) -> Digest
+{
+    Sha256::new().chain_update([1]).chain_update(left).chain_update(right).finalize().into()
+}
+
+/// Returns the levels of the Merkle tree of the fragments, from the leaves to the root. The last node of
+/// a level with an odd number of nodes goes up to the next level as is
+fn merkle_levels(fragments : &[Vec<u8>]) -> Vec<Vec<Digest>>
+{
+    let mut levels = vec![fragments.iter().map(|data| hash_leaf(data)).collect::<Vec<Digest>>()];
+    while levels[levels.len() - 1].len() > 1
+    {
+        let next = levels[levels.len() - 1].chunks(2)
+            .map(|pair| match pair
+            {
+                [left, right] => { hash_node(left, right) }
+                _ => { pair[0] }
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Returns the proof that the fragment at `index` belongs to the tree of the given levels
+fn merkle_proof(levels : &[Vec<Digest>], index : usize) -> Vec<Digest>
+{
+    let mut index = index;
+    let mut proof = Vec::new();
+    for level in &levels[..levels.len() - 1]
+    {
+        if let Some(sibling) = level.get(index ^ 1)
+        {
+            proof.push(*sibling);
+        }
+        index /= 2;
+    }
+    proof
+}
+
+impl Fragment
+{
+    /// Checks that the fragment is the one of server `id` in the Merkle tree of `nb_servers` fragments
+    /// of the given root
+    pub fn verify(&self, root : &Digest, id : ProcId, nb_servers : usize) -> bool
+    {
+        if id == 0 || self.index != id - 1 || self.index as usize >= nb_servers
+        {
+            return false;
+        }
+
+        let mut hash = hash_leaf(&self.data);
+        let mut siblings = self.proof.iter();
+        let (mut index, mut level_size) = (self.index as usize, nb_servers);
+        while level_size > 1
+        {
+            if (index ^ 1) < level_size
+            {
+                let sibling = match siblings.next()
+                {
+                    Some(sibling) => { sibling }
+                    None => { return false }
+                };
+                hash = match index % 2
+                {
+                    0 => { hash_node(&hash, sibling) }
+                    _ => { hash_node(sibling, &hash) }
+                };
+            }
+            index /= 2;
+            level_size = level_size.div_ceil(2);
+        }
+        siblings.next().is_none() && hash == *root
+    }
+}
+
+/// Returns the Merkle root of the fragments of the given dependencies
+pub fn dependencies_root(dependencies : &[Transaction], nb_servers : usize, fault_threshold : usize) -> Digest
+{
+    let levels = merkle_levels(&encode(dependencies, nb_servers, fault_threshold));
+    levels[levels.len() - 1][0]
+}
+
+/// A dispersal broadcast a process takes part to: a reliable broadcast of the transaction and of the
+/// root of its fragments, whose Echo messages carry the fragments
+pub struct DispersalBroadcast
+{
+    /// Id of the process taking part to the broadcast
+    proc_id : ProcId,
+    /// Number of servers, which is the number of fragments
+    nb_servers : usize,
+    /// Greatest number of byzantine servers
+    fault_threshold : usize,
+    /// Reliable broadcast of the transaction and of the root
+    broadcast : Broadcast,
+    /// Fragment received with the Echo message of each server, by index, with its root. Only the first
+    /// fragment of a server is kept, so that a byzantine server can not fill the memory with fragments of
+    /// other roots
+    fragments : HashMap<u32, (Digest, Vec<u8>)>,
+    /// Message delivered by the reliable broadcast, with its certificate, until its dependencies are rebuilt
+    pending : Option<(Message, Box<DeliveryCertificate>)>,
+    /// Variable stating if the message was delivered with its dependencies
+    delivered : bool,
+    /// Variable stating if the fragments of the delivered root do not give back the same root
+    inconsistent : bool,
+}
+
+impl DispersalBroadcast
+{
+    pub fn new(proc_id : ProcId, weights : Weights) -> DispersalBroadcast
+    {
+        DispersalBroadcast
+        {
+            proc_id,
+            nb_servers : weights.servers().len(),
+            fault_threshold : weights.fault_threshold() as usize,
+            broadcast : init_broadcast(proc_id, weights),
+            fragments : HashMap::new(),
+            pending : None,
+            delivered : false,
+            inconsistent : false,
+        }
+    }
+
+    /// Keeps the fragment of an Echo message if its proof is valid, and returns the message without it.
+    /// Returns None if the fragment is missing or invalid
+    fn keep_fragment(&mut self, mut message : Message) -> Option<Message>
+    {
+        let (root, fragment) = match (message.dependencies_root, message.fragment.take())
+        {
+            (Some(root), Some(fragment)) if fragment.verify(&root, message.sender_id, self.nb_servers) => { (root, fragment) }
+            _ => { return None }
+        };
+        self.fragments.entry(fragment.index).or_insert((root, fragment.data));
+        Some(message)
+    }
+
+    /// Delivers the message delivered by the reliable broadcast once enough fragments of its root were
+    /// received to rebuild its dependencies
+    fn try_deliver(&mut self) -> Vec<Action>
+    {
+        let (message, certificate) = match &self.pending
+        {
+            Some(pending) if !self.delivered && !self.inconsistent => { pending }
+            _ => { return vec![] }
+        };
+        let root = match message.dependencies_root
+        {
+            Some(root) => { root }
+            None => { return vec![] }
+        };
+        let fragments : HashMap<u32, &[u8]> = self.fragments.iter()
+            .filter(|(_, (fragment_root, _))| *fragment_root == root)
+            .map(|(index, (_, data))| (*index, &data[..]))
+            .collect();
+        if fragments.len() < nb_data_fragments(self.nb_servers, self.fault_threshold)
+        {
+            return vec![];
+        }
+
+        // The dependencies must give back the same fragments, otherwise the origin did not encode them correctly
+        match decode(&fragments, self.nb_servers, self.fault_threshold)
+        {
+            Some(dependencies) if dependencies_root(&dependencies, self.nb_servers, self.fault_threshold) == root =>
+            {
+                log!("Rebuilt the dependencies of the message from {} fragments.", fragments.len());
+                self.delivered = true;
+                let mut message = message.clone();
+                message.dependencies = dependencies;
+                vec![Action::Deliver(message, certificate.clone())]
+            }
+            _ =>
+            {
+                log!("The fragments of the message do not match their root, the origin did not encode them correctly!");
+                self.inconsistent = true;
+                vec![]
+            }
+        }
+    }
+}
+
+impl BroadcastInstance for DispersalBroadcast
+{
+    fn start(&mut self, init : &Message, origin_signature : &[u8]) -> Vec<Action>
+    {
+        // The origin encodes the dependencies, and sends its fragment to each server
+        if init.fragment.is_none() && init.origin == self.proc_id
+        {
+            let fragments = encode(&init.dependencies, self.nb_servers, self.fault_threshold);
+            let levels = merkle_levels(&fragments);
+            let root = levels[levels.len() - 1][0];
+            log!("Dispersing {} fragments of {} bytes", fragments.len(), fragments[0].len());
+
+            let mut actions = Vec::new();
+            let mut own_init = None;
+            for (index, data) in fragments.into_iter().enumerate()
+            {
+                let mut init_msg = init.clone();
+                init_msg.dependencies = vec![];
+                init_msg.dependencies_root = Some(root);
+                init_msg.fragment = Some(Fragment { index : index as u32, data, proof : merkle_proof(&levels, index) });
+                match index as ProcId + 1 == self.proc_id
+                {
+                    true => { own_init = Some(init_msg); }
+                    false => { actions.push(Action::SendTo(index as ProcId + 1, init_msg)); }
+                }
+            }
+            if let Some(own_init) = own_init
+            {
+                actions.extend(self.broadcast.start(&own_init, origin_signature));
+            }
+            return actions;
+        }
+
+        // The other servers echo their fragment, if it is the one committed to
+        match (init.dependencies_root, &init.fragment)
+        {
+            (Some(root), Some(fragment)) if init.dependencies.is_empty()
+                && fragment.verify(&root, self.proc_id, self.nb_servers) =>
+            {
+                self.broadcast.start(init, origin_signature)
+            }
+            _ =>
+            {
+                log!("Received an init message without a valid fragment for me!");
+                vec![]
+            }
+        }
+    }
+
+    fn add_message(&mut self, message : Message, signature : Vec<u8>) -> Vec<Action>
+    {
+        let message = match message.message_type
+        {
+            MessageType::Echo => match self.keep_fragment(message)
+            {
+                Some(message) => { message }
+                None =>
+                {
+                    log!("Received an echo message without a valid fragment!");
+                    return vec![];
+                }
+            },
+            // Only Echo messages carry a fragment, so that the signatures of Ready messages prove the delivery
+            _ if message.fragment.is_some() =>
+            {
+                log!("Received {} message from {} carrying a fragment!", message.message_type, message.sender_id);
+                return vec![];
+            }
+            _ => { message }
+        };
+
+        let mut actions = Vec::new();
+        for action in self.broadcast.add_message(message, signature)
+        {
+            match action
+            {
+                // Ready messages only carry the root
+                Action::Broadcast(mut ready_msg) =>
+                {
+                    ready_msg.fragment = None;
+                    actions.push(Action::Broadcast(ready_msg));
+                }
+                Action::Deliver(message, certificate) => { self.pending = Some((message, certificate)); }
+                action => { actions.push(action); }
+            }
+        }
+        actions.extend(self.try_deliver());
+        actions
+    }
+
+    fn started(&self) -> Instant
+    {
+        self.broadcast.started()
+    }
+
+    fn is_delivered(&self) -> bool
+    {
+        self.delivered
+    }
+
+    fn stuck_reason(&self) -> &'static str
+    {
+        match (&self.pending, self.inconsistent)
+        {
+            (_, true) => { "fragments not matching their root" }
+            (Some(_), false) => { "not enough fragments" }
+            (None, false) => { self.broadcast.stuck_reason() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_utils;
+
+    /// Numbers of servers tested, trees with an odd number of leaves included
+    const NB_SERVERS : [usize; 6] = [2, 3, 4, 5, 7, 256];
+
+    /// Returns `nb_dependencies` incoming transactions of an account
+    fn dependencies(nb_dependencies : u32) -> Vec<Transaction>
+    {
+        let account = test_utils::account();
+        (1..=nb_dependencies).map(|seq_id| Transaction::from(seq_id, account, account, seq_id)).collect()
+    }
+
+    /// Returns the fragments at the given indexes
+    fn select(fragments : &[Vec<u8>], indexes : impl Iterator<Item = usize>) -> HashMap<u32, &[u8]>
+    {
+        indexes.map(|index| (index as u32, &fragments[index][..])).collect()
+    }
+
+    #[test]
+    fn dependencies_are_rebuilt_from_any_f_plus_one_fragments()
+    {
+        for nb_servers in NB_SERVERS
+        {
+            let fault_threshold = (nb_servers - 1) / 3;
+            let nb_data = nb_data_fragments(nb_servers, fault_threshold);
+            for nb_dependencies in [0, 1, 50]
+            {
+                let dependencies = dependencies(nb_dependencies);
+                let fragments = encode(&dependencies, nb_servers, fault_threshold);
+                assert_eq!(fragments.len(), nb_servers);
+
+                let first = select(&fragments, 0..nb_data);
+                let last = select(&fragments, nb_servers - nb_data..nb_servers);
+                assert_eq!(decode(&first, nb_servers, fault_threshold), Some(dependencies.clone()));
+                assert_eq!(decode(&last, nb_servers, fault_threshold), Some(dependencies.clone()));
+                assert_eq!(decode(&select(&fragments, 1..nb_data), nb_servers, fault_threshold), None);
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_fragments_do_not_give_back_the_dependencies()
+    {
+        for nb_servers in NB_SERVERS
+        {
+            let fault_threshold = (nb_servers - 1) / 3;
+            let nb_data = nb_data_fragments(nb_servers, fault_threshold);
+            let dependencies = dependencies(50);
+            let root = dependencies_root(&dependencies, nb_servers, fault_threshold);
+            let mut fragments = encode(&dependencies, nb_servers, fault_threshold);
+
+            // A fragment of another size can not be decoded
+            let mut shorter = fragments.clone();
+            shorter[0].pop();
+            assert_eq!(decode(&select(&shorter, 0..nb_data), nb_servers, fault_threshold), None);
+
+            // A changed payload gives other dependencies, whose root is not the committed one
+            fragments[0][LENGTH_SIZE] ^= 1;
+            if let Some(rebuilt) = decode(&select(&fragments, 0..nb_data), nb_servers, fault_threshold)
+            {
+                assert_ne!(rebuilt, dependencies);
+                assert_ne!(dependencies_root(&rebuilt, nb_servers, fault_threshold), root);
+            }
+
+            // So does a length which does not fit in the fragments
+            fragments[0][..LENGTH_SIZE].copy_from_slice(&u64::MAX.to_le_bytes());
+            assert_eq!(decode(&select(&fragments, 0..nb_data), nb_servers, fault_threshold), None);
+        }
+    }
+
+    #[test]
+    fn merkle_proofs_are_only_valid_for_their_fragment()
+    {
+        for nb_servers in NB_SERVERS
+        {
+            let fault_threshold = (nb_servers - 1) / 3;
+            let fragments = encode(&dependencies(10), nb_servers, fault_threshold);
+            let levels = merkle_levels(&fragments);
+            let root = levels[levels.len() - 1][0];
+            assert_eq!(root, dependencies_root(&dependencies(10), nb_servers, fault_threshold));
+
+            for (index, data) in fragments.iter().enumerate()
+            {
+                let fragment = Fragment { index : index as u32, data : data.clone(), proof : merkle_proof(&levels, index) };
+                let id = index as ProcId + 1;
+                assert!(fragment.verify(&root, id, nb_servers));
+
+                assert!(!fragment.verify(&[0; 32], id, nb_servers));
+                assert!(!fragment.verify(&root, id % nb_servers as ProcId + 1, nb_servers));
+                assert!(!fragment.verify(&root, 0, nb_servers));
+
+                let mut tampered = fragment.clone();
+                tampered.data[0] ^= 1;
+                assert!(!tampered.verify(&root, id, nb_servers));
+
+                let mut shorter = fragment.clone();
+                shorter.proof.pop();
+                assert!(!shorter.verify(&root, id, nb_servers));
+
+                let mut longer = fragment.clone();
+                longer.proof.push(root);
+                assert!(!longer.verify(&root, id, nb_servers));
+
+                // The fragment of a server does not hold for another one, unless both fragments are the same
+                let mut moved = fragment.clone();
+                moved.index = (index as u32 + 1) % nb_servers as u32;
+                if fragments[moved.index as usize] != moved.data
+                {
+                    assert!(!moved.verify(&root, moved.index as ProcId + 1, nb_servers));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Errors raised when decoding data which can not be trusted
+//!
+//! Frames received from clients and servers, signatures and the files of the data directory can
+//! all be malformed. Decoding them never panics: it returns a [`DecodeError`], which is logged by the
+//! caller before the data is rejected.
+
+use std::fmt::{Display, Formatter};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use crate::framing::MAX_FRAME_SIZE;
+
+/// Reason why some data could not be decoded
+#[derive(Debug)]
+pub enum DecodeError
+{
+    /// The bytes are not the bincode encoding of the expected type
+    Malformed(bincode::Error),
+    /// The bytes are not an ed25519 signature
+    MalformedSignature,
+    /// The signature is not the one of the expected key
+    InvalidSignature,
+    /// A field of a history or seq file could not be parsed
+    InvalidField { line: usize, field: &'static str, value: String },
+    /// A line of a history file does not have the expected number of fields
+    MissingField { line: usize, field: &'static str },
+    /// The file could not be read
+    Io(String),
+}
+
+impl Display for DecodeError
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            DecodeError::Malformed(err) => { write!(f, "malformed data ({})", err) }
+            DecodeError::MalformedSignature => { write!(f, "malformed signature") }
+            DecodeError::InvalidSignature => { write!(f, "invalid signature") }
+            DecodeError::InvalidField { line, field, value } =>
+            {
+                write!(f, "invalid {} '{}' on line {}", field, value, line)
+            }
+            DecodeError::MissingField { line, field } => { write!(f, "missing {} on line {}", field, line) }
+            DecodeError::Io(err) => { write!(f, "{}", err) }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Deserializes bytes received from the network or read from a file.
+/// The encoding is the one of [`bincode::serialize`], and the decoded value can not be bigger than a frame
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError>
+{
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_FRAME_SIZE as u64)
+        .deserialize(bytes)
+        .map_err(DecodeError::Malformed)
+}
@@ -0,0 +1,13 @@
+//! Events handled by the main loop of a node
+
+use crate::crypto::SignedMessage;
+use crate::instructions::RespInstruction;
+
+/// Everything that can wake up the main loop of a node
+pub enum Event
+{
+    /// A message received from another server
+    Message(SignedMessage),
+    /// An instruction received from a client
+    Instruction(RespInstruction),
+}
@@ -0,0 +1,190 @@
+//! Length-prefixed framing used on the wire between nodes
+//!
+//! Every frame is made of a 4 bytes big-endian header giving the length of the payload, followed
+//! by the payload itself. A stream can carry any number of frames, and a frame can be split over
+//! several reads: the [`FrameDecoder`] buffers the incoming bytes until a whole frame is available.
+
+use std::io::{Error, ErrorKind, Read, Write};
+
+/// Size of the header of a frame
+pub const HEADER_SIZE: usize = 4;
+/// Maximal size of the payload of a frame (16 MiB)
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+/// Size of the buffer used to read from a stream
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Returns the given payload prefixed with its length
+pub fn encode_frame(payload: &[u8]) -> Result<Vec<u8>, Error>
+{
+    if payload.len() > MAX_FRAME_SIZE
+    {
+        return Err(Error::new(ErrorKind::InvalidInput,
+            format!("Frame of {} bytes exceeds the maximal size of {} bytes", payload.len(), MAX_FRAME_SIZE)));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Writes the given payload as a single frame on the stream
+pub fn write_frame<W: Write>(stream: &mut W, payload: &[u8]) -> Result<(), Error>
+{
+    let frame = encode_frame(payload)?;
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Reassembles frames from bytes received in arbitrary chunks
+pub struct FrameDecoder
+{
+    /// Bytes received but not yet returned as a frame
+    buffer: Vec<u8>,
+}
+
+impl Default for FrameDecoder
+{
+    fn default() -> FrameDecoder
+    {
+        FrameDecoder::new()
+    }
+}
+
+impl FrameDecoder
+{
+    pub fn new() -> FrameDecoder
+    {
+        FrameDecoder
+        {
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Adds received bytes to the decoder
+    pub fn push(&mut self, bytes: &[u8])
+    {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame if there is one.
+    /// Fails if the announced length of the frame is bigger than [`MAX_FRAME_SIZE`], in which
+    /// case the stream can not be trusted anymore and should be closed
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, Error>
+    {
+        if self.buffer.len() < HEADER_SIZE
+        {
+            return Ok(None);
+        }
+
+        let mut header = [0; HEADER_SIZE];
+        header.copy_from_slice(&self.buffer[..HEADER_SIZE]);
+        let length = u32::from_be_bytes(header) as usize;
+        if length > MAX_FRAME_SIZE
+        {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("Announced frame of {} bytes exceeds the maximal size of {} bytes", length, MAX_FRAME_SIZE)));
+        }
+
+        if self.buffer.len() < HEADER_SIZE + length
+        {
+            return Ok(None);
+        }
+
+        let frame = self.buffer[HEADER_SIZE..HEADER_SIZE + length].to_vec();
+        self.buffer.drain(..HEADER_SIZE + length);
+        Ok(Some(frame))
+    }
+
+    /// Reads from the stream until a complete frame is available.
+    /// Returns `Ok(None)` if the stream was closed by the other side
+    pub fn read_frame<R: Read>(&mut self, stream: &mut R) -> Result<Option<Vec<u8>>, Error>
+    {
+        let buf = &mut [0; READ_BUFFER_SIZE];
+        loop
+        {
+            if let Some(frame) = self.next_frame()?
+            {
+                return Ok(Some(frame));
+            }
+
+            let received = stream.read(buf)?;
+            if received < 1
+            {
+                if !self.buffer.is_empty()
+                {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "Stream closed in the middle of a frame"));
+                }
+                return Ok(None);
+            }
+            self.push(&buf[..received]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn partial_frame_is_reassembled()
+    {
+        let frame = encode_frame(b"hello world").unwrap();
+        let mut decoder = FrameDecoder::new();
+
+        decoder.push(&frame[..2]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.push(&frame[2..7]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.push(&frame[7..]);
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"hello world".to_vec()));
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn several_frames_in_one_read()
+    {
+        let mut bytes = encode_frame(b"first").unwrap();
+        bytes.extend(encode_frame(b"").unwrap());
+        bytes.extend(encode_frame(b"third").unwrap());
+        let last = encode_frame(b"fourth").unwrap();
+        bytes.extend_from_slice(&last[..3]);
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&bytes);
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"first".to_vec()));
+        assert_eq!(decoder.next_frame().unwrap(), Some(vec![]));
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"third".to_vec()));
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.push(&last[3..]);
+        assert_eq!(decoder.next_frame().unwrap(), Some(b"fourth".to_vec()));
+    }
+
+    #[test]
+    fn oversize_frame_is_rejected()
+    {
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&((MAX_FRAME_SIZE + 1) as u32).to_be_bytes());
+        assert_eq!(decoder.next_frame().unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&(MAX_FRAME_SIZE as u32).to_be_bytes());
+        assert!(decoder.next_frame().unwrap().is_none());
+
+        assert_eq!(encode_frame(&vec![0; MAX_FRAME_SIZE + 1]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn read_frame_reports_truncated_stream()
+    {
+        let mut bytes = encode_frame(b"whole").unwrap();
+        bytes.extend_from_slice(&encode_frame(b"truncated").unwrap()[..6]);
+        let mut stream = &bytes[..];
+
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(decoder.read_frame(&mut stream).unwrap(), Some(b"whole".to_vec()));
+        assert_eq!(decoder.read_frame(&mut stream).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(FrameDecoder::new().read_frame(&mut &[][..]).unwrap(), None);
+    }
+}
@@ -0,0 +1,411 @@
+//! Sample-based gossip broadcast, for networks of many servers (Contagion, see *Scalable Byzantine
+//! Reliable Broadcast*, Guerraoui, Kuznetsov, Monti, Pavlovič and Seredinschi, DISC 2019)
+//!
+//! Instead of talking to every server, a process only talks to random samples of the servers, chosen
+//! for each broadcast. The broadcast is built out of three layers:
+//! - Murmur : a process relays the Init message it receives first to its gossip sample ;
+//! - Sieve : a process subscribes to the Echo messages of its echo sample, and is ready to deliver a
+//!   message once `echo_threshold` of them echoed it ;
+//! - Contagion : a process then sends a Ready message to the processes subscribed to it, and subscribes
+//!   to the Ready messages of its ready and delivery samples. It sends its Ready message as well once
+//!   `ready_threshold` processes of its ready sample sent one, and delivers the message once
+//!   `delivery_threshold` processes of its delivery sample sent one.
+//!
+//! With samples of `O(log n)` servers, a process sends `O(log n)` messages per transaction. The
+//! properties of the reliable broadcast only hold with a probability which grows with the size of the
+//! samples, and the samples ignore the weights of the servers. Since a sample is private to a process,
+//! the signatures it gathers do not prove the delivery to anyone else.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use crate::broadcast::{Action, BroadcastInstance};
+use crate::certificate::DeliveryCertificate;
+use crate::log;
+use crate::message::{Message, MessageType};
+use crate::process::ProcId;
+
+/// Sizes of the samples of a gossip broadcast, and numbers of messages of a sample a process waits for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GossipParameters
+{
+    /// Number of process an Init message is relayed to
+    pub gossip_sample : usize,
+    /// Number of process a process subscribes to the Echo messages of
+    pub echo_sample : usize,
+    /// Number of Echo messages of the echo sample after which a process sends its Ready message
+    pub echo_threshold : usize,
+    /// Number of process whose Ready messages make a process send its own
+    pub ready_sample : usize,
+    /// Number of Ready messages of the ready sample after which a process sends its own
+    pub ready_threshold : usize,
+    /// Number of process whose Ready messages make a process deliver
+    pub delivery_sample : usize,
+    /// Number of Ready messages of the delivery sample after which a process delivers
+    pub delivery_threshold : usize,
+}
+
+impl GossipParameters
+{
+    /// Returns the parameters of a network of `nb_servers` servers, with samples of `4⌈log2(n+1)⌉`
+    /// servers at most
+    pub fn for_network(nb_servers : usize) -> GossipParameters
+    {
+        let log_n = (usize::BITS - nb_servers.leading_zeros()) as usize;
+        GossipParameters::with_sample_size(nb_servers.min(4 * log_n))
+    }
+
+    /// Returns the parameters with samples of `sample_size` servers. A process waits for more than 2/3
+    /// of the Echo messages of its echo sample, more than 1/3 of the Ready messages of its ready sample,
+    /// and more than 2/3 of the Ready messages of its delivery sample
+    pub fn with_sample_size(sample_size : usize) -> GossipParameters
+    {
+        GossipParameters
+        {
+            gossip_sample : sample_size,
+            echo_sample : sample_size,
+            echo_threshold : (2 * sample_size) / 3 + 1,
+            ready_sample : sample_size,
+            ready_threshold : sample_size / 3 + 1,
+            delivery_sample : sample_size,
+            delivery_threshold : (2 * sample_size) / 3 + 1,
+        }
+    }
+
+    /// Checks that the samples can be drawn from `nb_servers` servers, and that the thresholds can be reached
+    pub fn check(&self, nb_servers : usize) -> Result<(), String>
+    {
+        let samples = [("gossip_sample", self.gossip_sample, 1), ("echo_sample", self.echo_sample, self.echo_threshold),
+            ("ready_sample", self.ready_sample, self.ready_threshold),
+            ("delivery_sample", self.delivery_sample, self.delivery_threshold)];
+        for (name, size, threshold) in samples.iter()
+        {
+            if *size > nb_servers
+            {
+                return Err(format!("The {} of {} servers is larger than the network of {} servers", name, size, nb_servers));
+            }
+            if *threshold == 0 || threshold > size
+            {
+                return Err(format!("The threshold of the {} of {} servers must be between 1 and {}", name, size, size));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A gossip broadcast a process takes part to
+pub struct GossipBroadcast
+{
+    /// Id of the process taking part to the broadcast
+    proc_id : ProcId,
+    /// Parameters of the broadcast
+    parameters : GossipParameters,
+    /// Process the Init message is relayed to
+    gossip_sample : Vec<ProcId>,
+    /// Process whose Echo messages count
+    echo_sample : BTreeSet<ProcId>,
+    /// Process whose Ready messages make the process send its own
+    ready_sample : BTreeSet<ProcId>,
+    /// Process whose Ready messages make the process deliver
+    delivery_sample : BTreeSet<ProcId>,
+    /// Process which subscribed to the Echo message of the process
+    echo_subscribers : BTreeSet<ProcId>,
+    /// Process which subscribed to the Ready message of the process
+    ready_subscribers : BTreeSet<ProcId>,
+    /// Echo message of the process, once it received the Init message
+    echo_message : Option<Message>,
+    /// Ready message of the process, once it sent it
+    ready_message : Option<Message>,
+    /// Echo messages received from the echo sample, by sender
+    echos : BTreeMap<ProcId, Message>,
+    /// Ready messages received from the ready and delivery samples, with their signature, by sender
+    ready : BTreeMap<ProcId, (Message, Vec<u8>)>,
+    /// Variable stating if the message was already delivered
+    delivered : bool,
+    /// Instant the broadcast started
+    started : Instant,
+}
+
+impl GossipBroadcast
+{
+    /// Creates the broadcast for process `proc_id`, drawing its samples amongst `servers`
+    pub fn new<R : Rng>(proc_id : ProcId, parameters : GossipParameters, servers : &[ProcId], rng : &mut R) -> GossipBroadcast
+    {
+        let mut sample = |size : usize| -> Vec<ProcId> { servers.choose_multiple(rng, size).cloned().collect() };
+        GossipBroadcast
+        {
+            proc_id,
+            parameters,
+            gossip_sample : sample(parameters.gossip_sample),
+            echo_sample : sample(parameters.echo_sample).into_iter().collect(),
+            ready_sample : sample(parameters.ready_sample).into_iter().collect(),
+            delivery_sample : sample(parameters.delivery_sample).into_iter().collect(),
+            echo_subscribers : BTreeSet::new(),
+            ready_subscribers : BTreeSet::new(),
+            echo_message : None,
+            ready_message : None,
+            echos : BTreeMap::new(),
+            ready : BTreeMap::new(),
+            delivered : false,
+            started : Instant::now(),
+        }
+    }
+
+    /// Sends the Ready message of the process for the given message to the process subscribed to it
+    fn send_ready(&mut self, message : &Message) -> Vec<Action>
+    {
+        log!("I am ready to accept a message. Sending it to my subscribers.");
+        let mut ready_msg = message.clone();
+        ready_msg.sender_id = self.proc_id;
+        ready_msg.message_type = MessageType::Ready;
+        ready_msg.echo_signatures = vec![];
+        self.ready_message = Some(ready_msg.clone());
+        self.ready_subscribers.iter().map(|id| Action::SendTo(*id, ready_msg.clone())).collect()
+    }
+
+    /// Returns the number of process of the given sample which sent a Ready message for the given message
+    fn nb_ready(&self, sample : &BTreeSet<ProcId>, message : &Message) -> usize
+    {
+        sample.iter().filter(|id| matches!(self.ready.get(id), Some((ready, _)) if ready == message)).count()
+    }
+}
+
+impl BroadcastInstance for GossipBroadcast
+{
+    fn start(&mut self, init : &Message, origin_signature : &[u8]) -> Vec<Action>
+    {
+        let mut actions = Vec::new();
+
+        // Relay the Init message, with the signature of its origin
+        let mut relayed_msg = init.clone();
+        relayed_msg.sender_id = self.proc_id;
+        if self.proc_id != init.origin
+        {
+            relayed_msg.origin_signature = origin_signature.to_vec();
+        }
+        for id in self.gossip_sample.iter().filter(|id| **id != self.proc_id)
+        {
+            actions.push(Action::SendTo(*id, relayed_msg.clone()));
+        }
+
+        // Echo the message to the process which already subscribed to it
+        let mut echo_msg = init.clone();
+        echo_msg.sender_id = self.proc_id;
+        echo_msg.message_type = MessageType::Echo;
+        for id in &self.echo_subscribers
+        {
+            actions.push(Action::SendTo(*id, echo_msg.clone()));
+        }
+        self.echo_message = Some(echo_msg);
+
+        // Then subscribe to the messages of the samples
+        let mut subscribe_msg = init.clone();
+        subscribe_msg.sender_id = self.proc_id;
+        subscribe_msg.message_type = MessageType::EchoSubscribe;
+        for id in &self.echo_sample
+        {
+            actions.push(Action::SendTo(*id, subscribe_msg.clone()));
+        }
+        subscribe_msg.message_type = MessageType::ReadySubscribe;
+        for id in self.ready_sample.union(&self.delivery_sample)
+        {
+            actions.push(Action::SendTo(*id, subscribe_msg.clone()));
+        }
+        log!("Relaying the init message to {} process and subscribing to {} process", self.gossip_sample.len(),
+            self.echo_sample.len() + self.ready_sample.union(&self.delivery_sample).count());
+        actions
+    }
+
+    fn add_message(&mut self, message : Message, signature : Vec<u8>) -> Vec<Action>
+    {
+        let sender_id = message.sender_id;
+        match message.message_type
+        {
+            MessageType::EchoSubscribe =>
+            {
+                log!("Process {} subscribed to my echo message", sender_id);
+                self.echo_subscribers.insert(sender_id);
+                self.echo_message.iter().map(|echo_msg| Action::SendTo(sender_id, echo_msg.clone())).collect()
+            }
+            MessageType::ReadySubscribe =>
+            {
+                log!("Process {} subscribed to my ready message", sender_id);
+                self.ready_subscribers.insert(sender_id);
+                self.ready_message.iter().map(|ready_msg| Action::SendTo(sender_id, ready_msg.clone())).collect()
+            }
+            MessageType::Echo if self.echo_sample.contains(&sender_id) =>
+            {
+                log!("Received an echo message from {}", sender_id);
+                self.echos.insert(sender_id, message.clone());
+                let nb_echos = self.echos.values().filter(|echo| **echo == message).count();
+                match self.ready_message.is_none() && nb_echos >= self.parameters.echo_threshold
+                {
+                    true => { self.send_ready(&message) }
+                    false => { vec![] }
+                }
+            }
+            MessageType::Ready if self.ready_sample.contains(&sender_id) || self.delivery_sample.contains(&sender_id) =>
+            {
+                log!("Received a ready message from {}", sender_id);
+                self.ready.insert(sender_id, (message.clone(), signature));
+                let mut actions = Vec::new();
+
+                if self.ready_message.is_none()
+                    && self.nb_ready(&self.ready_sample, &message) >= self.parameters.ready_threshold
+                {
+                    actions.extend(self.send_ready(&message));
+                }
+
+                if !self.delivered && self.nb_ready(&self.delivery_sample, &message) >= self.parameters.delivery_threshold
+                {
+                    log!("Enough ready messages of my delivery sample. I can add the message to transactions to process.");
+                    self.delivered = true;
+                    let signatures : Vec<(ProcId, Vec<u8>)> = self.delivery_sample.iter()
+                        .filter_map(|id| match self.ready.get(id)
+                        {
+                            Some((ready, signature)) if *ready == message => { Some((*id, signature.clone())) }
+                            _ => { None }
+                        })
+                        .collect();
+                    let certificate = DeliveryCertificate::from(&message, MessageType::Ready, signatures);
+                    actions.push(Action::Deliver(message, Box::new(certificate)));
+                }
+                actions
+            }
+            message_type =>
+            {
+                log!("Received {} message from {} which does not count for my samples", message_type, sender_id);
+                vec![]
+            }
+        }
+    }
+
+    fn started(&self) -> Instant
+    {
+        self.started
+    }
+
+    fn is_delivered(&self) -> bool
+    {
+        self.delivered
+    }
+
+    fn stuck_reason(&self) -> &'static str
+    {
+        match self.ready_message
+        {
+            Some(_) => { "not enough ready messages from the delivery sample" }
+            None => { "not enough echo messages from the echo sample" }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use encoins_api::base_types::Currency;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use crate::test_utils;
+
+    /// Returns the ids of a network of `nb_servers` servers
+    fn servers(nb_servers : ProcId) -> Vec<ProcId>
+    {
+        (1..=nb_servers).collect()
+    }
+
+    /// Returns the message of type `message_type` server `sender_id` sends in a broadcast of `amount` encoins
+    fn message(message_type : MessageType, sender_id : ProcId, amount : Currency) -> Message
+    {
+        test_utils::message(message_type, sender_id, 1, amount)
+    }
+
+    /// Returns the servers the actions send a message of the given type to, and whether they deliver
+    fn sent(actions : &[Action], message_type : MessageType) -> (Vec<ProcId>, bool)
+    {
+        let sent = actions.iter()
+            .filter_map(|action| match action
+            {
+                Action::SendTo(id, message) if message.message_type == message_type => { Some(*id) }
+                _ => { None }
+            })
+            .collect();
+        (sent, actions.iter().any(|action| matches!(action, Action::Deliver(..))))
+    }
+
+    #[test]
+    fn samples_grow_with_the_logarithm_of_the_network()
+    {
+        let parameters = GossipParameters::for_network(100);
+        assert_eq!(parameters, GossipParameters::with_sample_size(28));
+        assert_eq!((parameters.echo_threshold, parameters.ready_threshold, parameters.delivery_threshold), (19, 10, 19));
+        assert!(parameters.check(100).is_ok());
+        assert!(parameters.check(27).is_err());
+
+        // Small networks are sampled entirely
+        assert_eq!(GossipParameters::for_network(4), GossipParameters::with_sample_size(4));
+        assert!(GossipParameters::with_sample_size(0).check(4).is_err());
+    }
+
+    #[test]
+    fn samples_only_depend_on_the_seed()
+    {
+        let parameters = GossipParameters::with_sample_size(10);
+        let samples = |seed : u64|
+        {
+            let broadcast = GossipBroadcast::new(1, parameters, &servers(50), &mut StdRng::seed_from_u64(seed));
+            (broadcast.gossip_sample, broadcast.echo_sample, broadcast.ready_sample, broadcast.delivery_sample)
+        };
+
+        let (gossip_sample, echo_sample, ready_sample, delivery_sample) = samples(0);
+        assert_eq!(samples(0), (gossip_sample.clone(), echo_sample.clone(), ready_sample.clone(), delivery_sample.clone()));
+        assert_ne!(samples(1).0, gossip_sample);
+
+        // Samples are made of distinct servers of the network
+        assert_eq!(gossip_sample.iter().collect::<BTreeSet<_>>().len(), 10);
+        for sample in [echo_sample, ready_sample, delivery_sample]
+        {
+            assert_eq!(sample.len(), 10);
+            assert!(sample.iter().all(|id| (1..=50).contains(id)));
+        }
+    }
+
+    #[test]
+    fn messages_of_the_samples_count_up_to_their_thresholds()
+    {
+        test_utils::initialize_logs();
+        // Samples of 4 servers amongst 4 are the whole network: 3 Echo messages make a process ready,
+        // 2 Ready messages make it send its own, and 3 make it deliver
+        let parameters = GossipParameters::with_sample_size(4);
+        let mut broadcast = GossipBroadcast::new(1, parameters, &servers(4), &mut StdRng::seed_from_u64(0));
+
+        let actions = broadcast.start(&message(MessageType::Init, 2, 10), &[]);
+        assert_eq!(sent(&actions, MessageType::Init), (vec![2, 3, 4], false));
+        assert_eq!(sent(&actions, MessageType::EchoSubscribe).0, vec![1, 2, 3, 4]);
+        assert_eq!(sent(&actions, MessageType::ReadySubscribe).0, vec![1, 2, 3, 4]);
+        assert!(broadcast.add_message(message(MessageType::ReadySubscribe, 3, 10), vec![]).is_empty());
+
+        // Echo messages of another transaction do not count
+        assert!(broadcast.add_message(message(MessageType::Echo, 2, 10), vec![]).is_empty());
+        assert!(broadcast.add_message(message(MessageType::Echo, 3, 20), vec![]).is_empty());
+        assert!(broadcast.add_message(message(MessageType::Echo, 4, 10), vec![]).is_empty());
+        let actions = broadcast.add_message(message(MessageType::Echo, 1, 10), vec![]);
+        assert_eq!(sent(&actions, MessageType::Ready), (vec![3], false));
+
+        assert!(broadcast.add_message(message(MessageType::Ready, 2, 10), vec![2]).is_empty());
+        assert!(broadcast.add_message(message(MessageType::Ready, 3, 20), vec![3]).is_empty());
+        assert!(broadcast.add_message(message(MessageType::Ready, 4, 10), vec![4]).is_empty());
+        let actions = broadcast.add_message(message(MessageType::Ready, 1, 10), vec![1]);
+        assert_eq!(sent(&actions, MessageType::Ready), (vec![], true));
+        assert!(broadcast.is_delivered());
+
+        // Ready messages of a process which did not echo make it send its own
+        let mut broadcast = GossipBroadcast::new(1, parameters, &servers(4), &mut StdRng::seed_from_u64(0));
+        assert!(broadcast.add_message(message(MessageType::ReadySubscribe, 4, 10), vec![]).is_empty());
+        assert!(broadcast.add_message(message(MessageType::Ready, 2, 10), vec![]).is_empty());
+        assert_eq!(sent(&broadcast.add_message(message(MessageType::Ready, 3, 10), vec![]), MessageType::Ready), (vec![4], false));
+        assert_eq!(broadcast.stuck_reason(), "not enough ready messages from the delivery sample");
+    }
+}
@@ -1,48 +1,216 @@
+use std::fmt::{Display, Formatter};
 use std::sync::mpsc::Sender;
+use chrono::Utc;
+use serde::{Serialize,Deserialize};
+use encoins_api::base_types::{SeqId, Transaction, UserId};
 use encoins_api::instruction::Instruction;
 use encoins_api::response::Response;
-use crate::process::Process;
+use crate::byzantine;
+use crate::certificate::DeliveryCertificate;
+use crate::process::{Process, ProcId};
 use crate::log;
+use crate::utils::{load_certificate, load_seq};
 
+/// Identifier chosen by a client for one of its requests
+pub type RequestId = u64;
+
+/// What a client can ask to a node
+#[derive(Serialize,Deserialize)]
+pub enum Request
+{
+    /// An [`Instruction`] of the encoins API. The signature of a [`Instruction::SignedTransfer`] does not
+    /// cover the sequence number of the transaction: the node gives it the next one, and the account can
+    /// not make the same transfer again this way (see [`TransferSigning::Legacy`](crate::crypto::TransferSigning::Legacy))
+    Instruction(Instruction),
+    /// A transfer of money, given by the transaction of the sending account and its signature by the owner
+    /// of the account (see [`sign_transaction`](crate::crypto::sign_transaction)). The client chooses the sequence number of the transaction,
+    /// which follows the last validated one of the account
+    Transfer { transaction : Transaction, signature : Vec<u8> },
+    /// The sequence number of the last validated transaction of account `user`
+    Seq { user : UserId },
+    /// The certificate proving the delivery of transaction `seq_id` of account `user`
+    Certificate { user : UserId, seq_id : SeqId },
+}
+
+/// What a node answers to a [`Request`]. A reply is serialized as soon as it is built, so its size does not matter
+#[derive(Serialize,Deserialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum Reply
+{
+    /// The [`Response`] to an [`Instruction`], signed by the node
+    Response(SignedResponse),
+    /// The requested certificate, or `None` if the node did not validate the transaction
+    Certificate(Option<DeliveryCertificate>),
+    /// The sequence number of the last validated transaction of the account, or `None` if the node
+    /// could not read it
+    Seq(Option<SeqId>),
+}
+
+/// A [`Response`] to an [`Instruction`], with what a client needs to attribute it to the node
+#[derive(Serialize,Deserialize)]
+pub struct NodeResponse
+{
+    /// Id of the node answering
+    pub node_id : ProcId,
+    /// Time at which the node answered, in milliseconds since the Unix epoch
+    pub timestamp : i64,
+    /// Id of the request answered
+    pub request_id : RequestId,
+    /// Account the instruction is about
+    pub user : UserId,
+    pub response : Response
+}
+
+/// A [`NodeResponse`] and its signature by the node, which the client can check with the public key
+/// of the node and keep as evidence of what the node answered
+#[derive(Serialize,Deserialize)]
+pub struct SignedResponse
+{
+    pub response : NodeResponse,
+    pub signature : Vec<u8>
+}
+
+/// A request sent by a client, tagged with an id so that the client can send several requests
+/// on the same connection without waiting for their responses
+#[derive(Serialize,Deserialize)]
+pub struct ClientRequest
+{
+    pub request_id : RequestId,
+    pub request : Request
+}
+
+/// The [`Reply`] to a [`ClientRequest`], tagged with the id of the request it answers.
+/// Replies can be sent back in a different order than the requests were received
+#[derive(Serialize,Deserialize)]
+pub struct ClientResponse
+{
+    pub request_id : RequestId,
+    pub reply : Reply
+}
 
 pub struct RespInstruction 
 {
-    pub instruction : Instruction,
-    pub resp_sender : Sender<Response>
+    pub request_id : RequestId,
+    pub request : Request,
+    pub resp_sender : Sender<ClientResponse>
+}
+
+impl Display for Request
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            Request::Instruction(instruction) => { write!(f, "{}", instruction) }
+            Request::Transfer { transaction, .. } => { write!(f, "Transfer {}", transaction) }
+            Request::Seq { user } => { write!(f, "Sequence number of {}", user) }
+            Request::Certificate { user, seq_id } => { write!(f, "Certificate of transaction {} of {}", seq_id, user) }
+        }
+    }
 }
 
 impl RespInstruction 
 {
-    pub fn from(instruction : Instruction, resp_sender : Sender<Response>) -> RespInstruction 
+    pub fn from(request : ClientRequest, resp_sender : Sender<ClientResponse>) -> RespInstruction 
     {
         RespInstruction 
         {
-            instruction,
+            request_id : request.request_id,
+            request : request.request,
             resp_sender
         }
     }
 }
 
+/// Sends back a reply to the client thread, tagged with the id of the request it answers
+fn respond(resp_sender : &Sender<ClientResponse>, request_id : RequestId, reply : Reply)
+{
+    let client_response = ClientResponse
+    {
+        request_id,
+        reply
+    };
+    if resp_sender.send(client_response).is_err()
+    {
+        log!("Client of request {} disconnected before receiving its response", request_id);
+    }
+}
+
+/// Sends back to the client the response to an instruction, signed by the node
+fn respond_signed(process : &Process, resp_sender : &Sender<ClientResponse>, request_id : RequestId, user : UserId,
+    response : Response)
+{
+    let node_response = NodeResponse
+    {
+        node_id : process.id,
+        timestamp : Utc::now().timestamp_millis(),
+        request_id,
+        user,
+        response
+    };
+    let signed_response = node_response.sign(process.get_key_pair());
+    respond(resp_sender, request_id, Reply::Response(signed_response));
+}
+
 pub fn deal_with_instruction(process: &mut Process, resp_instruction : RespInstruction) 
 {
-    let instruction = resp_instruction.instruction;
+    let request_id = resp_instruction.request_id;
     let resp_sender = resp_instruction.resp_sender;
-    match instruction 
+    match resp_instruction.request 
     {
-        Instruction::Balance {user} => 
+        Request::Instruction(Instruction::Balance {user}) => 
         {
             log!("balance incoming");
             let balance = process.output_balance_for(user);
-            resp_sender.send(Response::Balance(balance))
-                .expect("the channel between the instruction thread and the server one is closed");
-
+            respond_signed(process, &resp_sender, request_id, user, Response::Balance(balance));
+        }
+        Request::Instruction(Instruction::SignedTransfer {transfer, signature}) => 
+        {
+            log!("transfer incoming, signed without its sequence number");
+            let user = transfer.sender;
+            let suceed = match process.legacy_transaction(transfer)
+            {
+                Ok(transaction) => { byzantine::transfer(process, transaction, signature) }
+                Err(code) => { (false, code) }
+            };
+            respond_signed(process, &resp_sender, request_id, user, Response::Transfer(suceed.0,suceed.1));
         }
-        Instruction::SignedTransfer {transfer,signature} => 
+        Request::Transfer {transaction, signature} =>
         {
             log!("transfer incoming");
-            let suceed = process.transfer(transfer, signature);
-            resp_sender.send(Response::Transfer(suceed.0,suceed.1))
-                .expect("the channel between the instruction thread and the server one is closed");
+            let user = transaction.sender_id;
+            let suceed = byzantine::transfer(process, transaction, signature);
+            respond_signed(process, &resp_sender, request_id, user, Response::Transfer(suceed.0,suceed.1));
+        }
+        Request::Seq {user} =>
+        {
+            log!("sequence number request incoming");
+            let seq_id = match load_seq(process.get_data_directory(), &user)
+            {
+                Ok(seq_id) => { Some(seq_id) }
+                Err(err) =>
+                {
+                    log!("Could not load the seq file of {} : {}", user, err);
+                    None
+                }
+            };
+            respond(&resp_sender, request_id, Reply::Seq(seq_id));
+        }
+        Request::Certificate {user, seq_id} =>
+        {
+            log!("certificate request incoming");
+            // The certificates of a broadcast which does not prove the delivery would never be valid
+            let certificate = match load_certificate(process.get_data_directory(), &user, seq_id)
+            {
+                Ok(_) if !process.get_broadcast_kind().proves_delivery() => { None }
+                Ok(certificate) => { certificate }
+                Err(err) =>
+                {
+                    log!("Could not load the certificate of transaction {} of {} : {}", seq_id, user, err);
+                    None
+                }
+            };
+            respond(&resp_sender, request_id, Reply::Certificate(certificate));
         }
     }
 }
@@ -0,0 +1,247 @@
+//! Persistent identity key of a node
+//!
+//! A node signs its messages and authenticates its channels with an ed25519 keypair. The keypair
+//! is generated the first time the node starts and saved in its data directory, so that the node
+//! keeps the same identity across restarts and its public key can be written in the configuration
+//! of the other nodes.
+//!
+//! The secret key is never written in clear: it is encrypted with ChaCha20-Poly1305 under a key
+//! derived from a passphrase with Argon2id. The passphrase is read, in this order, from the
+//! `ENCOINS_PASSPHRASE` environment variable, from the file named by the `ENCOINS_PASSPHRASE_FILE`
+//! environment variable, or from a prompt. The public key is kept in clear, so that it can be read
+//! without the passphrase, but it is authenticated by the encryption.
+
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use argon2::Argon2;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Serialize, Deserialize};
+use zeroize::Zeroizing;
+use crate::crypto::create_keypair;
+use crate::errors::decode;
+use crate::log;
+
+/// Name of the file holding the encrypted secret key in the data directory of a node
+const KEYSTORE_FILE: &str = "node_key";
+/// Environment variable holding the passphrase of the keystore
+const PASSPHRASE_VARIABLE: &str = "ENCOINS_PASSPHRASE";
+/// Environment variable holding the path to a file containing the passphrase of the keystore
+const PASSPHRASE_FILE_VARIABLE: &str = "ENCOINS_PASSPHRASE_FILE";
+/// Version of the format of the keystore
+const KEYSTORE_VERSION: u8 = 1;
+/// Size of the salt of the key derivation
+const SALT_SIZE: usize = 16;
+/// Size of the key derived from the passphrase
+const KEY_SIZE: usize = 32;
+/// Size of the nonce of the encryption
+const NONCE_SIZE: usize = 12;
+
+/// Content of the keystore file
+#[derive(Serialize, Deserialize)]
+struct EncryptedKeystore
+{
+    version: u8,
+    /// Public key of the node, authenticated by the encryption
+    public_key: Vec<u8>,
+    /// Salt used to derive the encryption key from the passphrase
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    /// Encrypted secret key
+    ciphertext: Vec<u8>,
+}
+
+/// Returns the keypair saved in the given data directory, generating and saving a new one if there is none
+pub fn load_or_create(directory: &str) -> Result<Keypair, String>
+{
+    let path = format!("{}/{}", directory, KEYSTORE_FILE);
+    if Path::new(&path).exists()
+    {
+        return load(&path);
+    }
+
+    let passphrase = read_passphrase(true)?;
+    let keypair = create_keypair();
+    let keystore = encrypt(&keypair, &passphrase)?;
+    save(&path, &keystore)?;
+    log!("Generated a new identity key, saved in {}", path);
+    Ok(keypair)
+}
+
+/// Returns the public key of the node whose data directory is given, generating a new keypair if there is none
+pub fn load_or_create_public_key(directory: &str) -> Result<PublicKey, String>
+{
+    let path = format!("{}/{}", directory, KEYSTORE_FILE);
+    if !Path::new(&path).exists()
+    {
+        return load_or_create(directory).map(|keypair| keypair.public);
+    }
+
+    let content = fs::read(&path).map_err(|e| format!("Could not read the keystore {} : {}", path, e))?;
+    match decode::<EncryptedKeystore>(&content)
+    {
+        Ok(keystore) =>
+        {
+            PublicKey::from_bytes(&keystore.public_key)
+                .map_err(|e| format!("The keystore {} does not hold a valid public key : {}", path, e))
+        }
+        // A keystore written in clear has to be encrypted first
+        Err(_) => { load(&path).map(|keypair| keypair.public) }
+    }
+}
+
+/// Returns the hexadecimal encoding of a public key, as written in the configuration of the network
+pub fn public_key_to_hex(public_key: &PublicKey) -> String
+{
+    hex::encode(public_key.to_bytes())
+}
+
+/// Reads and decrypts the keypair saved at the given path
+fn load(path: &str) -> Result<Keypair, String>
+{
+    let content = fs::read(path).map_err(|e| format!("Could not read the keystore {} : {}", path, e))?;
+    let keystore: EncryptedKeystore = match decode(&content)
+    {
+        Ok(keystore) => { keystore }
+        Err(_) => { return encrypt_clear_keystore(path, &content); }
+    };
+    if keystore.version != KEYSTORE_VERSION
+    {
+        return Err(format!("The keystore {} has an unknown version {}", path, keystore.version));
+    }
+
+    let passphrase = read_passphrase(false)?;
+    decrypt(&keystore, &passphrase).map_err(|e| format!("Could not unlock the keystore {} : {}", path, e))
+}
+
+/// Encrypts in place a keystore written in clear by a previous version, and returns its keypair
+fn encrypt_clear_keystore(path: &str, content: &[u8]) -> Result<Keypair, String>
+{
+    let hex_secret = Zeroizing::new(String::from_utf8_lossy(content).trim().to_string());
+    let secret_bytes = Zeroizing::new(hex::decode(hex_secret.as_bytes())
+        .map_err(|_| format!("The keystore {} is corrupted", path))?);
+    let secret = SecretKey::from_bytes(&secret_bytes)
+        .map_err(|e| format!("The keystore {} does not hold a valid secret key : {}", path, e))?;
+    let public = PublicKey::from(&secret);
+    let keypair = Keypair { secret, public };
+
+    log!("The keystore {} is not encrypted, encrypting it", path);
+    let passphrase = read_passphrase(true)?;
+    let keystore = encrypt(&keypair, &passphrase)?;
+    let new_path = format!("{}.new", path);
+    save(&new_path, &keystore)?;
+    fs::rename(&new_path, path).map_err(|e| format!("Could not replace the keystore {} : {}", path, e))?;
+    Ok(keypair)
+}
+
+/// Encrypts the secret key of a keypair with the given passphrase
+fn encrypt(keypair: &Keypair, passphrase: &str) -> Result<EncryptedKeystore, String>
+{
+    let mut salt = [0; SALT_SIZE];
+    let mut nonce = [0; NONCE_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let public_key = keypair.public.to_bytes();
+    let secret = Zeroizing::new(keypair.secret.to_bytes());
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&derive_key(passphrase, &salt)?[..]));
+    let ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: &secret[..], aad: &public_key })
+        .map_err(|_| String::from("Could not encrypt the secret key"))?;
+
+    Ok(EncryptedKeystore
+    {
+        version: KEYSTORE_VERSION,
+        public_key: public_key.to_vec(),
+        salt: salt.to_vec(),
+        nonce: nonce.to_vec(),
+        ciphertext,
+    })
+}
+
+/// Decrypts the keypair of a keystore with the given passphrase
+fn decrypt(keystore: &EncryptedKeystore, passphrase: &str) -> Result<Keypair, String>
+{
+    if keystore.nonce.len() != NONCE_SIZE || keystore.public_key.len() != PUBLIC_KEY_LENGTH
+    {
+        return Err(String::from("the keystore is corrupted"));
+    }
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&derive_key(passphrase, &keystore.salt)?[..]));
+    let payload = Payload { msg: &keystore.ciphertext[..], aad: &keystore.public_key[..] };
+    let secret_bytes = Zeroizing::new(cipher.decrypt(Nonce::from_slice(&keystore.nonce), payload)
+        .map_err(|_| String::from("wrong passphrase or corrupted keystore"))?);
+    if secret_bytes.len() != SECRET_KEY_LENGTH
+    {
+        return Err(String::from("the keystore is corrupted"));
+    }
+
+    let secret = SecretKey::from_bytes(&secret_bytes).map_err(|e| e.to_string())?;
+    let public = PublicKey::from(&secret);
+    if public.as_bytes()[..] != keystore.public_key[..]
+    {
+        return Err(String::from("the secret key does not match the public key"));
+    }
+    Ok(Keypair { secret, public })
+}
+
+/// Derives the encryption key of the keystore from the passphrase
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; KEY_SIZE]>, String>
+{
+    let mut key = Zeroizing::new([0; KEY_SIZE]);
+    Argon2::default().hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .map_err(|e| format!("Could not derive a key from the passphrase : {}", e))?;
+    Ok(key)
+}
+
+/// Reads the passphrase of the keystore. When prompting for a new passphrase, it is asked twice
+fn read_passphrase(new: bool) -> Result<Zeroizing<String>, String>
+{
+    if let Ok(passphrase) = env::var(PASSPHRASE_VARIABLE)
+    {
+        return Ok(Zeroizing::new(passphrase));
+    }
+
+    if let Ok(path) = env::var(PASSPHRASE_FILE_VARIABLE)
+    {
+        let content = Zeroizing::new(fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read the passphrase file {} : {}", path, e))?);
+        return Ok(Zeroizing::new(content.trim_end_matches(['\n', '\r']).to_string()));
+    }
+
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Passphrase of the node keystore: ")
+        .map_err(|e| format!("Could not read the passphrase : {}", e))?);
+    if new
+    {
+        let confirmation = Zeroizing::new(rpassword::prompt_password("Confirm the passphrase: ")
+            .map_err(|e| format!("Could not read the passphrase : {}", e))?);
+        if passphrase != confirmation
+        {
+            return Err(String::from("The passphrases do not match"));
+        }
+    }
+    Ok(passphrase)
+}
+
+/// Saves the keystore at the given path, which only its owner can read
+fn save(path: &str, keystore: &EncryptedKeystore) -> Result<(), String>
+{
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let serialized = bincode::serialize(keystore).expect("Problem with the serialization of a keystore");
+    let mut file = options.open(path)
+        .map_err(|e| format!("Could not create the keystore {} : {}", path, e))?;
+    file.write_all(&serialized)
+        .map_err(|e| format!("Could not write the keystore {} : {}", path, e))
+}
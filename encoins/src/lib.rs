@@ -0,0 +1,30 @@
+//! A node of the encoins cryptocurrency
+//!
+//! The `encoins` binary runs a node, or a simulated network of nodes. The modules of the node are
+//! also exposed as a library so that they can be used by the fuzz targets.
+
+pub mod utils;
+pub mod message;
+pub mod messaging;
+pub mod process;
+pub mod crypto;
+pub mod client_network;
+pub mod instructions;
+pub mod serv_network;
+pub mod broadcast;
+pub mod signed_echo;
+pub mod gossip;
+pub mod dispersal;
+pub mod yaml;
+pub mod framing;
+pub mod connection_pool;
+pub mod secure_channel;
+pub mod event;
+pub mod transport;
+pub mod simulation;
+pub mod byzantine;
+pub mod keystore;
+pub mod certificate;
+pub mod errors;
+#[cfg(test)]
+mod test_utils;
@@ -1,49 +1,100 @@
-extern crate core;
 use std::{env, thread};
 use std::collections::HashMap;
+use std::mem;
 use std::sync::mpsc;
-use std::sync::mpsc::Receiver;
-use crate::client_network::client_listener;
-use crate::serv_network::server_listener;
-use crate::process::Process;
-use encoins_api::base_types::UserId;
-use crate::broadcast::Broadcast;
-use crate::instructions::RespInstruction;
-use crate::crypto::{SignedMessage, create_keypair};
-
-mod utils;
-mod message;
-mod messaging;
-mod process;
-mod crypto;
-mod client_network;
-mod instructions;
-mod serv_network;
-mod broadcast;
-mod yaml;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use ed25519_dalek::PublicKey;
+use yaml_rust::yaml::Hash;
+use encoins::client_network::client_listener;
+use encoins::process::Process;
+use encoins::broadcast::{BroadcastKind, OngoingBroadcasts, Weight, Weights};
+use encoins::byzantine::Behaviour;
+use encoins::crypto::SignedMessage;
+use encoins::event::Event;
+use encoins::secure_channel::ChannelKeys;
+use encoins::transport::TcpTransport;
+use encoins::{byzantine, crash_with, crypto, dispersal, instructions, keystore, log, messaging, process, simulation, utils, yaml};
+
+/// Maximal number of messages of other servers whose signatures are checked together
+const MAX_BATCH_SIZE: usize = 64;
+/// Time between two searches for broadcasts which did not terminate within their lifetime
+const EVICTION_PERIOD: Duration = Duration::from_secs(1);
+/// Configuration of the network
+const NET_CONFIG_FILE: &str = "encoins-config/net_config.yml";
 
 fn main()
 {
     // Get given arguments at execution
     let args: Vec<String> = env::args().collect();
 
-    // Check if logs have to be written
-    let write_logs = match args.get(1) {
-        Some(bool) => match bool.parse::<bool>()
+    // Run a simulated network instead of a node
+    if args.get(1).map(|arg| &arg[..]) == Some("simulate")
+    {
+        match simulation::SimulationConfig::from_args(&args[2..])
         {
-            Ok(b) => { b }
-            Err(_) => { false }
+            Ok(config) =>
+            {
+                let all_held = simulation::run(&config);
+                std::process::exit(if all_held { 0 } else { 1 });
+            }
+            Err(err) =>
+            {
+                eprintln!("{}\nUsage: {}", err, simulation::USAGE);
+                std::process::exit(2);
+            }
         }
+    }
+
+    // Print the public key of the node, to be written in the configuration of the other nodes
+    if args.get(1).map(|arg| &arg[..]) == Some("public-key")
+    {
+        utils::initialize(false, None, node_id());
+        utils::set_print_logs(false);
+        match keystore::load_or_create_public_key(&utils::main_directory_path())
+        {
+            Ok(public_key) => { println!("{}", keystore::public_key_to_hex(&public_key)); }
+            Err(err) =>
+            {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Generate the identity key of the node if it has none, and write its public key in the configuration
+    if args.get(1).map(|arg| &arg[..]) == Some("setup")
+    {
+        let proc_id = node_id();
+        utils::initialize(false, None, proc_id);
+        utils::set_print_logs(false);
+        let written = keystore::load_or_create_public_key(&utils::main_directory_path())
+            .map(|public_key| keystore::public_key_to_hex(&public_key))
+            .and_then(|public_key| yaml::write_public_key(NET_CONFIG_FILE, proc_id, &public_key).map(|_| public_key));
+        match written
+        {
+            Ok(public_key) => { println!("Public key of server {} written in {} : {}", proc_id, NET_CONFIG_FILE, public_key); }
+            Err(err) =>
+            {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Check if logs have to be written
+    let write_logs = match args.get(1) {
+        Some(bool) => bool.parse::<bool>().unwrap_or_default(),
         None => true
     };
 
     // Load network parameters
-    let proc_id: u32 = env::var("NUM_NODE")
-        .expect("No environment variable NUM_NODE found")
-        .parse::<u32>()
-        .expect("Environment variable NUM_NODE is not an int");
+    let proc_id = node_id();
 
-    let hash_net_config = yaml::yaml_to_hash("encoins-config/net_config.yml");
+    let hash_net_config = yaml::yaml_to_hash(NET_CONFIG_FILE);
     let number_of_processes = yaml::read_network_parameters(&hash_net_config);
 
     // Initialize logging
@@ -52,59 +103,183 @@ fn main()
     log!("Initializing with {} processes", number_of_processes);
 
     // Initialize threads
-    let (mut proc,serv_net_receiver,instruction_receiver) = initialize_node(number_of_processes,proc_id);
-    let mut ongoing_broadcasts : HashMap<UserId, Broadcast> = HashMap::new();
+    let (mut proc,event_receiver) = initialize_node(&hash_net_config,number_of_processes,proc_id);
+    let broadcast_lifetime = yaml::read_broadcast_lifetime(&hash_net_config);
+    let mut ongoing_broadcasts : OngoingBroadcasts = OngoingBroadcasts::new(broadcast_lifetime);
+    let mut next_eviction = Instant::now() + EVICTION_PERIOD;
 
     loop
     {
-        // First check messages with other processes from network
-        let comm = serv_net_receiver.try_recv();
-        match comm 
+        // Wait for something to happen, or for the next eviction of stuck broadcasts
+        let mut messages : Vec<SignedMessage> = Vec::new();
+        match event_receiver.recv_timeout(next_eviction.saturating_duration_since(Instant::now()))
         {
-            Ok(message) => 
+            Ok(event) => { handle_event(&mut proc, event, &mut messages, &mut ongoing_broadcasts); }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) =>
             {
-                messaging::deal_with_message(&mut proc, message, &mut ongoing_broadcasts)
+                panic!("Every thread sending events to the main thread has stopped");
             }
-            Err(_) => {}
-        };
+        }
 
-        // Then check instruction from client
-        let resp_instruction = instruction_receiver.try_recv();
-        match resp_instruction 
+        // Then deal with everything else which is already waiting
+        while let Ok(event) = event_receiver.try_recv()
         {
-            Ok(resp_instruc) => 
-            { 
-                log!("Received instruction : {}",resp_instruc.instruction);
-                instructions::deal_with_instruction(&mut proc, resp_instruc);
-            }
-            Err(_) => {}
-        };
+            handle_event(&mut proc, event, &mut messages, &mut ongoing_broadcasts);
+        }
+        byzantine::deal_with_messages(&mut proc, messages, &mut ongoing_broadcasts);
 
+        // Forget the broadcasts which did not terminate in time
+        if Instant::now() >= next_eviction
+        {
+            messaging::evict_expired_broadcasts(&mut proc, &mut ongoing_broadcasts, Instant::now());
+            next_eviction = Instant::now() + EVICTION_PERIOD;
+        }
+
+        // Only does something if new transactions were delivered
         proc.valid();
-        thread::sleep(std::time::Duration::from_millis(200));
+    }
+}
+
+/// Returns the id of the node, given by the environment variable NUM_NODE
+fn node_id() -> u32
+{
+    env::var("NUM_NODE")
+        .expect("No environment variable NUM_NODE found")
+        .parse::<u32>()
+        .expect("Environment variable NUM_NODE is not an int")
+}
+
+/// Dispatches an event to the function dealing with it. Messages of other servers are kept in
+/// `messages` so that their signatures are checked together, by batches of at most [`MAX_BATCH_SIZE`]
+fn handle_event(proc: &mut Process, event: Event, messages: &mut Vec<SignedMessage>,
+    ongoing_broadcasts: &mut OngoingBroadcasts)
+{
+    match event
+    {
+        Event::Message(message) =>
+        {
+            messages.push(message);
+            if messages.len() >= MAX_BATCH_SIZE
+            {
+                byzantine::deal_with_messages(proc, mem::take(messages), ongoing_broadcasts);
+            }
+        }
+        Event::Instruction(resp_instruc) =>
+        {
+            // The messages received before the instruction are dealt with first
+            byzantine::deal_with_messages(proc, mem::take(messages), ongoing_broadcasts);
+            log!("Received instruction : {}",resp_instruc.request);
+            instructions::deal_with_instruction(proc, resp_instruc);
+        }
     }
 }
 
 /// Function that initializes threads. Each thread runs the code for one Processus.
-fn initialize_node(nb_process: u32, proc_id : u32) -> (Process,Receiver<SignedMessage>,Receiver<RespInstruction>){
+fn initialize_node(hash_net_config: &Hash, nb_process: u32, proc_id : u32) -> (Process,Receiver<Event>){
+
+    // Load the identity of the node, used to authenticate its messages
+    let keypair = match keystore::load_or_create(&utils::main_directory_path())
+    {
+        Ok(keypair) => { keypair }
+        Err(err) => { crash_with!("Could not load the identity key of the node : {}", err); }
+    };
+    log!("Public key of the node : {}", keystore::public_key_to_hex(&keypair.public));
+
+    // Network information
+    let (ip, port_server, port_client) = yaml::read_server_address(hash_net_config, proc_id);
+    let client_socket: (String, u16) = (ip.clone(), port_client);
+    let server_socket: (String, u16) = (ip.clone(), port_server);
+    let mut serv_addr : Vec<(String, u16)> = Vec::new();
+    let mut public_keys : Vec<PublicKey> = Vec::new();
+    let mut weights : Vec<Weight> = Vec::new();
+    for i in 1..nb_process+1
+    {
+        let (ip, port_server, _) = yaml::read_server_address(hash_net_config, i);
+        serv_addr.push((ip, port_server));
+        match yaml::read_public_key(hash_net_config, i)
+        {
+            Some(public_key) => { public_keys.push(public_key); }
+            None if i == proc_id =>
+            {
+                crash_with!("The public key of server {} is not set in the configuration. Run the setup command \
+                    to write the key of the node in it", i);
+            }
+            None =>
+            {
+                crash_with!("The public key of server {} is not set in the configuration. Write the key printed \
+                    by the public-key command of server {} in it", i, i);
+            }
+        }
+        weights.push(yaml::read_weight(hash_net_config, i));
+    }
+
+    // The broadcast is only safe if the byzantine servers weigh less than a third of the network
+    let weights = match yaml::read_fault_threshold(hash_net_config)
+    {
+        Some(fault_threshold) => { Weights::from(weights).and_then(|weights| weights.with_fault_threshold(fault_threshold)) }
+        None => { Weights::from(weights) }
+    };
+    let weights = match weights
+    {
+        Ok(weights) => { weights }
+        Err(err) => { crash_with!("Refusing to start : {}", err); }
+    };
+    log!("Tolerating byzantine servers of weight {} out of {}", weights.fault_threshold(), weights.total());
+
+    // The samples of a gossip broadcast are drawn amongst the servers, and a dispersal broadcast sends
+    // one fragment per server
+    let broadcast_kind = yaml::read_broadcast_kind(hash_net_config, nb_process);
+    let checked = match broadcast_kind
+    {
+        BroadcastKind::Gossip(parameters) => { parameters.check(nb_process as usize) }
+        BroadcastKind::Dispersal => { dispersal::check(&weights) }
+        _ => { Ok(()) }
+    };
+    if let Err(err) = checked
+    {
+        crash_with!("Refusing to start : {}", err);
+    }
+    log!("Broadcasting transactions with the {} broadcast", broadcast_kind);
 
-    // Create public/private key pairs to authenticate messages
-    let keypair = create_keypair();
+    // The other servers only trust the key declared in the configuration
+    if public_keys[proc_id as usize - 1] != keypair.public
+    {
+        crash_with!("The public key of server {} in the configuration is not the one of its keystore. \
+            Run the setup command to write the key of the node in the configuration", proc_id);
+    }
 
-    // Init the communication channels and a process
-    let (serv_net_sender,serv_net_receiver) = mpsc::channel();
-    let (instruction_sender,instruction_receiver) = mpsc::channel();
-    let proc = process::Process::init(proc_id, nb_process, keypair);
+    // Init the communication channel and the transport. The channels between servers are
+    // authenticated with the same identity as the messages
+    let (event_sender,event_receiver) = mpsc::channel();
+    let peer_keys : HashMap<u32, PublicKey> = (1..nb_process+1).zip(public_keys.iter().cloned()).collect();
+    let channel_keys = Arc::new(ChannelKeys::new(proc_id, crypto::copy_keypair(&keypair), peer_keys));
+    log!("Client_socket :{:?}",client_socket);
+    log!("Serv_socket :{:?}",server_socket);
+    let transport = TcpTransport::start(server_socket, &serv_addr, channel_keys, event_sender.clone());
+
+    // A node is correct unless it is asked to behave as a byzantine one
+    let behaviour = match env::var(byzantine::BYZANTINE_MODE_VARIABLE)
+    {
+        Ok(mode) => match Behaviour::from_name(&mode)
+        {
+            Ok(behaviour) => { behaviour }
+            Err(err) => { crash_with!("{}", err); }
+        }
+        Err(_) => { Behaviour::Correct }
+    };
+    if !behaviour.is_correct()
+    {
+        log!("Starting as a byzantine node in mode {}", behaviour);
+    }
 
+    // Init the process
+    let proc = process::Process::init(proc_id, nb_process, keypair, public_keys, weights, broadcast_kind, Box::new(transport),
+        utils::main_directory_path(), behaviour);
     log!("Server initialized correctly!");
-    log!("Client_socket :{:?}",proc.client_socket);
-    log!("Serv_socket :{:?}",proc.server_socket);
 
-    // Launch the communication threads
-    let client_socket = proc.get_client_socket();
-    thread::spawn( move ||{client_listener(client_socket, instruction_sender);});
-    let server_socket = proc.get_server_socket();
-    thread::spawn( move ||{server_listener(server_socket, serv_net_sender);});
+    // Launch the client thread
+    thread::spawn( move ||{client_listener(client_socket, event_sender);});
 
-    (proc,serv_net_receiver,instruction_receiver)
+    (proc,event_receiver)
 }
@@ -3,9 +3,10 @@ use serde::{Serialize,Deserialize};
 use std::fmt::{Display, Formatter};
 use encoins_api::base_types::{Transaction};
 use crate::crypto::SignedMessage;
+use crate::dispersal::{Digest, Fragment};
 use crate::process::ProcId;
 
-/// A message is composed of a transaction, the dependencies needed to validate a
+/// A message is composed of a transaction signed by the owner of the account, the dependencies needed to validate a
 /// transaction and a message type
 #[derive(Clone,Debug,Serialize,Deserialize)]
 pub struct Message
@@ -18,9 +19,24 @@ pub struct Message
     pub message_type: MessageType,
     /// Id of the process sending the message
     pub sender_id : ProcId,
+    /// Id of the process which started the broadcast of the transaction
+    pub origin : ProcId,
+    /// Signature of the transaction by the owner of the sending account
+    pub transfer_signature : Vec<u8>,
+    /// Signatures of the Echo messages gathered by the origin of a consistent broadcast, only carried by
+    /// Final messages
+    pub echo_signatures : Vec<(ProcId, Vec<u8>)>,
+    /// Signature of the Init message by its origin, only carried by Init messages relayed by other process
+    pub origin_signature : Vec<u8>,
+    /// Merkle root of the fragments of the dependencies, only set by a dispersal broadcast, whose messages
+    /// then carry no dependencies
+    pub dependencies_root : Option<Digest>,
+    /// Fragment of the dependencies of the receiver of an Init message or of the sender of an Echo message
+    /// of a dispersal broadcast
+    pub fragment : Option<Fragment>,
 }
 
-/// A MessageType can be Init, Echo or Ready and is used by the messaging
+/// A MessageType can be Init, Echo, Ready, Final or a subscription and is used by the messaging
 /// system to evaluate the state of the broadcast
 #[derive(Clone,Copy,Debug, PartialEq,Serialize,Deserialize)]
 pub enum MessageType
@@ -30,15 +46,44 @@ pub enum MessageType
     /// States that the message is an echo of a previous message sent by a process
     Echo,
     /// States that a process is ready to start processing the given message's content
-    Ready
+    Ready,
+    /// States that the origin of a consistent broadcast gathered the Echo messages of a quorum of process
+    Final,
+    /// Asks a process of the echo sample of a gossip broadcast to send its Echo message
+    EchoSubscribe,
+    /// Asks a process of the ready or delivery sample of a gossip broadcast to send its Ready messages
+    ReadySubscribe,
+}
+
+impl Message
+{
+    /// Creates a message of the given type for a transaction, carrying none of the fields only some
+    /// messages carry
+    pub fn new(transaction : Transaction, dependencies : Vec<Transaction>, message_type : MessageType, sender_id : ProcId,
+        origin : ProcId, transfer_signature : Vec<u8>) -> Message
+    {
+        Message
+        {
+            transaction,
+            dependencies,
+            message_type,
+            sender_id,
+            origin,
+            transfer_signature,
+            echo_signatures : vec![],
+            origin_signature : vec![],
+            dependencies_root : None,
+            fragment : None,
+        }
+    }
 }
 
 impl Display for SignedMessage
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result 
     {
-        write!(f, " (Transaction : {} , sender_id : {}, message type : {} )",
-            self.message.transaction, self.message.sender_id, self.message.message_type)
+        write!(f, " (Transaction : {} , sender_id : {}, origin : {}, message type : {} )",
+            self.message.transaction, self.message.sender_id, self.message.origin, self.message.message_type)
     }
 }
 
@@ -51,6 +96,9 @@ impl Display for MessageType
             MessageType::Init => { write!(f, "Init") }
             MessageType::Echo => { write!(f, "Echo") }
             MessageType::Ready => { write!(f, "Ready") }
+            MessageType::Final => { write!(f, "Final") }
+            MessageType::EchoSubscribe => { write!(f, "EchoSubscribe") }
+            MessageType::ReadySubscribe => { write!(f, "ReadySubscribe") }
         }
     }
 }
@@ -58,9 +106,11 @@ impl Display for MessageType
 impl PartialEq<Self> for Message
 {
     /// Implementation of equality for [`Message`]
-    /// Two messages are equal iff their transaction and dependencies are equal
+    /// Two messages are equal iff their transaction, origin, dependencies and root of the fragments of
+    /// their dependencies are equal
     fn eq(&self, other: &Self) -> bool 
     {
-        return (self.transaction == other.transaction) && (self.dependencies == other.dependencies)
+        (self.transaction == other.transaction) && (self.origin == other.origin)
+            && (self.dependencies == other.dependencies) && (self.dependencies_root == other.dependencies_root)
     }
 }
\ No newline at end of file
@@ -1,93 +1,281 @@
 //! A simple module to manage communications between processes
 
-use std::collections::HashMap;
-use encoins_api::base_types::UserId;
+use std::collections::VecDeque;
+use std::time::Instant;
+use ed25519_dalek::PublicKey;
 use crate::message::{MessageType};
-use crate::{Broadcast, log};
-use crate::broadcast::init_broadcast;
-use crate::process::Process;
-use crate::crypto::SignedMessage;
+use crate::broadcast::{Action, BroadcastId, OngoingBroadcasts};
+use crate::certificate::DeliveryCertificate;
+use crate::log;
+use crate::process::{Process, TRANSFER_WINDOW};
+use crate::crypto::{SignedMessage, verif_sigs};
+use crate::transport::Transport;
+use crate::utils::load_seq;
 
 /// A simple broadcast function to make a basic broadcast to all [`Processus`]
-pub fn broadcast( server_addr : &Vec<(String, u16)> , message : SignedMessage)
+pub fn broadcast( transport : &dyn Transport , message : SignedMessage)
 {
-    for addr in server_addr {
-        let message_copy = message.clone();
-        crate::serv_network::send(addr, message_copy);
+    transport.broadcast(message);
+}
+
+/// Checks the signatures of messages received from other servers, and returns the messages which
+/// are signed by the process they claim to come from. The others are logged and dropped
+pub fn verify_messages(process: &Process, signed_messages: Vec<SignedMessage>) -> Vec<SignedMessage>
+{
+    // Messages claiming to come from an unknown process can not be verified
+    let (signed_messages, public_keys): (Vec<SignedMessage>, Vec<&PublicKey>) = signed_messages.into_iter()
+        .filter_map(|signed_message| match process.get_public_key(signed_message.message.sender_id)
+        {
+            Some(public_key) => { Some((signed_message, public_key)) }
+            None =>
+            {
+                log!("Received a message from unknown process {}, dropping it", signed_message.message.sender_id);
+                None
+            }
+        })
+        .unzip();
+
+    let results = verif_sigs(&signed_messages, &public_keys);
+    signed_messages.into_iter().zip(results)
+        .filter_map(|(signed_message, result)| match result
+        {
+            Ok(()) => { Some(signed_message) }
+            Err(err) =>
+            {
+                log!("Received a message on behalf of process {} with a wrong signature ({}), dropping it",
+                    signed_message.message.sender_id, err);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Forgets the broadcasts which are older than their lifetime at instant `now`. The transfers the process
+/// broadcast for the transactions which were not delivered are released, so that their clients can ask
+/// for them again. What the process echoed is only forgotten once the transactions are validated. The
+/// messages of the forgotten broadcasts the transport did not send yet are dropped
+pub fn evict_expired_broadcasts(process: &mut Process, ongoing_broadcasts: &mut OngoingBroadcasts, now: Instant)
+{
+    for (broadcast_id, reason) in ongoing_broadcasts.evict_expired(now)
+    {
+        if let Some(reason) = reason
+        {
+            log!("Evicting the broadcast of {} ({})", broadcast_id, reason);
+            process.release_transfers(&broadcast_id.account, broadcast_id.seq_id);
+        }
+        process.get_transport().forget(broadcast_id);
+    }
+    for broadcast_id in ongoing_broadcasts.forget_validated(|account| load_seq(process.get_data_directory(), account).ok())
+    {
+        process.get_transport().forget(broadcast_id);
+    }
+}
+
+/// Carries out what the instance of a broadcast asks the process to do. The messages the process sends
+/// to every server count for the instance as soon as they are sent
+fn carry_out(process: &mut Process, broadcast_id: BroadcastId, actions: Vec<Action>,
+    ongoing_broadcasts: &mut OngoingBroadcasts)
+{
+    let mut actions: VecDeque<Action> = actions.into();
+    while let Some(action) = actions.pop_front()
+    {
+        match action
+        {
+            Action::Broadcast(message) =>
+            {
+                let signed_message = message.sign(process.get_key_pair());
+                broadcast(process.get_transport(), signed_message.clone());
+                if let Some(instance) = ongoing_broadcasts.get_mut(&broadcast_id)
+                {
+                    actions.extend(instance.add_message(signed_message.message, signed_message.signature));
+                }
+            }
+            Action::SendTo(to, message) =>
+            {
+                process.get_transport().send(to, message.sign(process.get_key_pair()));
+            }
+            Action::Deliver(message, certificate) =>
+            {
+                // The broadcast is kept until its lifetime ends, so that the late messages of its
+                // instance are not taken for early ones, and its Init message is not echoed again
+                log!("Delivered {}", broadcast_id);
+
+                // Save the message, with the proof of its delivery
+                process.in_to_validate(message, *certificate);
+            }
+        }
     }
 }
 
-/// Utility functions used by a [`Processus`] to deal with an incoming [`Message`]
-pub(crate) fn deal_with_message(process: &mut Process, signed_message: SignedMessage,
-    ongoing_broadcasts: &mut HashMap<UserId, Broadcast>)
+/// Utility functions used by a [`Processus`] to deal with an incoming [`Message`], whose signature
+/// was checked by [`verify_messages`]
+pub fn deal_with_message(process: &mut Process, signed_message: SignedMessage,
+    ongoing_broadcasts: &mut OngoingBroadcasts)
 {
     let proc_id = process.id;
-    let msg = signed_message.message;
+    let SignedMessage { message: msg, signature } = signed_message;
     match msg.message_type
     {
         MessageType::Init =>
+        {
+            let broadcast_id = BroadcastId::of(&msg);
+            // A process echoes one transaction per sequence number of an account, so that a byzantine
+            // process can not make it echo two transactions spending the same money
+            if ongoing_broadcasts.contains_key(&broadcast_id)
+            {
+                log!("There is already an ongoing broadcast of {}, ignoring the Init message of process {}!",
+                    broadcast_id, msg.origin);
+                return;
+            }
+
+            // A process can only start its own broadcasts, so an Init message relayed by another process
+            // must carry the signature of its origin
+            let (msg, origin_signature) = match msg.origin == msg.sender_id
+            {
+                true => { (msg, signature) }
+                false =>
+                {
+                    let relay_id = msg.sender_id;
+                    let mut origin_msg = msg;
+                    origin_msg.sender_id = origin_msg.origin;
+                    let origin_signature = std::mem::take(&mut origin_msg.origin_signature);
+                    let origin_signed_msg = SignedMessage { message: origin_msg.clone(), signature: origin_signature.clone() };
+                    let origin_signed = match process.get_public_key(origin_msg.origin)
+                    {
+                        Some(public_key) => { origin_signed_msg.verif_sig(public_key).is_ok() }
+                        None => { false }
+                    };
+                    if !origin_signed
+                    {
+                        log!("Process {} relayed an Init message which was not signed by process {}!", relay_id, origin_msg.origin);
+                        return;
+                    }
+                    (origin_msg, origin_signature)
+                }
+            };
 
-            match ongoing_broadcasts.contains_key(&msg.transaction.sender_id)
+            // Messages can be sent again by peers which missed their acknowledgement, so an
+            // Init of an already validated transaction is ignored. Transactions too far ahead of the
+            // validated ones are ignored too, so that an account can not have too many of them in flight
+            match load_seq(process.get_data_directory(), &msg.transaction.sender_id)
             {
-                true =>
+                Ok(seq_id) if msg.transaction.seq_id <= seq_id =>
                 {
-                    // Only one broadcast per account is allowed at the same time
-                    log!("There is already an ongoing broadcast from user id {}!", msg.sender_id);
+                    log!("Transaction {} was already validated!", msg.transaction);
                     return;
                 }
-                false =>
+                Ok(seq_id) if msg.transaction.seq_id > seq_id + TRANSFER_WINDOW =>
                 {
-                    // Create the broadcast instance
-                    let nb_process = (process.nb_process + 1) as usize;
-                    ongoing_broadcasts.insert(msg.transaction.sender_id,
-                        init_broadcast(msg.sender_id as usize, nb_process ));
-                    log!("Started broadcast for account id {}", msg.sender_id);
-
-                    // Echo the message
-                    let mut echo_msg = msg.clone();
-                    echo_msg.sender_id = proc_id;
-                    echo_msg.message_type = MessageType::Echo;
-                    let signed_echo_msg = echo_msg.sign(process.get_key_pair());
-                    log!("Broadcasting echo message to everyone!");
-                    broadcast(&process.get_serv_addr(), signed_echo_msg);
+                    log!("Transaction {} is too far ahead of the validated ones!", msg.transaction);
+                    return;
                 }
+                Ok(_) => {}
+                Err(err) =>
+                {
+                    log!("Could not load the seq file of {} : {}", msg.transaction.sender_id, err);
+                    return;
+                }
+            }
+
+            // Only the owner of an account can spend from it
+            if !msg.verif_transfer_signature()
+            {
+                log!("Transaction {} was not signed by the owner of the account!", msg.transaction);
+                return;
+            }
+
+            // Create the broadcast instance, which echoes the message. An instance which does nothing with
+            // the message refused it, and is not kept so that the transaction can still be broadcast
+            let mut instance = ongoing_broadcasts.new_instance(process.get_broadcast_kind(), proc_id, msg.origin,
+                process.get_weights().clone());
+            let actions = instance.start(&msg, &origin_signature);
+            if actions.is_empty()
+            {
+                log!("The Init message of {} from process {} was refused!", broadcast_id, msg.origin);
+                return;
+            }
+
+            // A broadcast given up can be started again, but only for the same transaction
+            if !ongoing_broadcasts.record_init(&msg)
+            {
+                log!("Transaction {} differs from the one with the same sequence number already echoed, ignoring the Init message of process {}!",
+                    msg.transaction, msg.origin);
+                return;
+            }
+            ongoing_broadcasts.insert(broadcast_id, instance);
+            log!("Started broadcast of {}", broadcast_id);
+            carry_out(process, broadcast_id, actions, ongoing_broadcasts);
+
+            // Then deal with the messages of the broadcast which arrived before its Init
+            for early_message in ongoing_broadcasts.take_early_messages(&broadcast_id)
+            {
+                log!("Replaying early message{}", early_message);
+                deal_with_message(process, early_message, ongoing_broadcasts);
             }
+        }
 
         _ =>
         {
-            match ongoing_broadcasts.get_mut(&msg.transaction.sender_id)
+            let broadcast_id = BroadcastId::of(&msg);
+
+            // A Final message proves the delivery of its message with the signatures it carries
+            if msg.message_type == MessageType::Final
             {
-                None =>
+                let certificate = DeliveryCertificate::from(&msg, MessageType::Echo, msg.echo_signatures.clone());
+                if !certificate.verify(process.get_public_keys(), process.get_weights())
                 {
-                    log!("No ongoing broadcast for proc id {} .", &msg.transaction.sender_id.to_string());
+                    log!("Final message of {} does not carry the signatures of a quorum of echoes!", broadcast_id);
+                    return;
                 }
-                Some(brb) =>
-                {
-                    log!("{}", brb.add_message(msg.clone()));
+            }
 
-                    if brb.is_ready() && !brb.ready_message_sent()
+            match ongoing_broadcasts.get_mut(&broadcast_id)
+            {
+                None =>
+                {
+                    // Messages of an already validated transaction can not belong to a broadcast to come, and
+                    // the ones of transactions too far ahead of the validated ones would have their Init ignored
+                    match load_seq(process.get_data_directory(), &broadcast_id.account)
                     {
-                        log!("I am ready to accept a message. Broadcasting it to everyone.");
-                        brb.set_ready_message_sent(true);
-                        let mut ready_msg = msg.clone();
-                        ready_msg.sender_id = proc_id;
-                        ready_msg.message_type = MessageType::Ready;
-                        let signed_rd_msg = ready_msg.sign(process.get_key_pair());
-                        broadcast(process.get_serv_addr(), signed_rd_msg);
+                        Ok(seq_id) if broadcast_id.seq_id <= seq_id =>
+                        {
+                            log!("No ongoing broadcast of {} .", broadcast_id);
+                            return;
+                        }
+                        Ok(seq_id) if broadcast_id.seq_id > seq_id + TRANSFER_WINDOW =>
+                        {
+                            log!("No ongoing broadcast of {}, which is too far ahead of the validated transactions.", broadcast_id);
+                            return;
+                        }
+                        Ok(_) => {}
+                        Err(err) =>
+                        {
+                            log!("Could not load the seq file of {} : {}", broadcast_id.account, err);
+                            return;
+                        }
                     }
 
-                    if brb.quorum_found()
+                    // Messages are only kept for broadcasts a server can start, so that their number is bounded
+                    let origin = msg.origin;
+                    if process.get_public_key(origin).is_none()
                     {
-                        log!("Quorum was achieved. I can add the message to transactions to process.");
-
-                        // Remove thr associated broadcast
-                        log!("Removing current transaction from ongoing broadcasts");
-                        ongoing_broadcasts.remove(&msg.transaction.sender_id);
+                        log!("No ongoing broadcast of {} and process {} does not exist.", broadcast_id, origin);
+                        return;
+                    }
 
-                        // Save the message
-                        process.in_to_validate(msg);
+                    // Otherwise the Init message of the broadcast may still be on its way
+                    log!("No ongoing broadcast of {} yet, keeping the message until it starts.", broadcast_id);
+                    let signed_message = SignedMessage { message: msg, signature };
+                    for dropped in ongoing_broadcasts.keep_early_message(signed_message)
+                    {
+                        log!("Too many early messages from process {}, dropping message{}", dropped.message.sender_id, dropped);
                     }
                 }
+                Some(instance) =>
+                {
+                    let actions = instance.add_message(msg, signature);
+                    carry_out(process, broadcast_id, actions, ongoing_broadcasts);
+                }
             }
         }
     }
@@ -2,18 +2,24 @@
 use ed25519_dalek::{PublicKey, Keypair};
 use std::collections::HashMap;
 use encoins_api::base_types::*;
-use encoins_api::transfer::Transfer;
 use crate::message::{Message, MessageType};
 use crate::messaging::broadcast;
-use crate::{crash_with, log};
-use crate::yaml::*;
-use crate::utils::{load_history, load_seq, write_transaction};
+use crate::log;
+use crate::byzantine::Behaviour;
+use crate::transport::Transport;
+use crate::utils::{load_history, load_seq, write_certificate, write_transaction};
+use crate::certificate::DeliveryCertificate;
+use encoins_api::transfer::Transfer;
+use crate::crypto::{transfer_signing, TransferSigning};
+use crate::broadcast::{BroadcastKind, Weights};
 
-type List = HashMap<UserId,u32>;
-type MessageSet = Vec<Message>;
+/// Type of a set of delivered messages, with the certificates proving their delivery
+type MessageSet = Vec<(Message, DeliveryCertificate)>;
 pub type ProcId = u32;
 /// Type of a set of transactions
 pub type TransferSet = Vec<Transaction>;
+/// Maximal number of transactions of an account which can be broadcast at the same time
+pub const TRANSFER_WINDOW : SeqId = 4;
 
 
 #[derive(Debug)]
@@ -23,134 +29,191 @@ pub struct Process
 {
     // Every process has a unique ID
     pub id : ProcId,
-    // List of size N such that rec(q) = number of delivered transfers from q
-    rec : List,
     // Set of last incoming transfers of local process
     deps : HashMap<UserId,TransferSet>,
     // Set of delivered (but not validated) transfers
     to_validate : MessageSet,
-    // List of N transmitters such that senders(q) is the transmitter that allow to communicate with process q
-    serv_addr : Vec<(String, u16)>,
-    // List of size N such that public_key(q) is the public_key of the process q
+    // Flag to know if transfers were delivered since the last validation
+    to_validate_changed : bool,
+    // Transport used to send messages to the processes, the process included
+    transport : Box<dyn Transport>,
+    // List of size N such that public_keys(q-1) is the public_key of the process q
     public_keys : Vec<PublicKey>,
-    // Keypair of private key required to sign messages and the public key associated with
+    // Voting weights of the processes
+    weights : Weights,
+    // Protocol used to broadcast transactions
+    broadcast_kind : BroadcastKind,
+    // Keypair of private key required to sign messages and the public key associated with.
+    // The private key is zeroized when the process is dropped
     secret_key : Keypair,
-    // Flags to know if the process has already send a transfer that it has not yet validate
-    ongoing_transfer : HashMap<UserId,bool>,
-    // Socket communicating with clients
-    pub client_socket : (String, u16),
-    // Socket communicating with servers
-    pub server_socket : (String, u16),
+    // Transactions the process broadcast for each account and has not yet validated, in sequence order
+    ongoing_transfers : HashMap<UserId,TransferSet>,
     // Number of servers
     pub nb_process : u32,
+    // Directory where the process writes the histories of the accounts
+    data_directory : String,
+    // Behaviour of the process, which is correct unless it is started in a byzantine mode
+    behaviour : Behaviour,
+    // Init message kept by an equivocating process for each account, until the client signs a conflicting transaction
+    held_init_messages : HashMap<UserId,Message>,
 }
 
 
 impl Process
 {
     /// Function which initialises a [Process]
-    pub fn init(id : ProcId, nb_process : u32, secret_key : Keypair) -> Process
+    #[allow(clippy::too_many_arguments)]
+    pub fn init(id : ProcId, nb_process : u32, secret_key : Keypair, public_keys : Vec<PublicKey>, weights : Weights,
+        broadcast_kind : BroadcastKind, transport : Box<dyn Transport>, data_directory : String, behaviour : Behaviour) -> Process
     {
-        // Network information
-        let hash_net_config = yaml_to_hash("encoins-config/net_config.yml");        
-        let (ip, port_server, port_client) = read_server_address(&hash_net_config, id);
-        
-        // Save the values
-        let client_socket: (String, u16) = (ip.clone(), port_client);
-        let server_socket: (String, u16) = (ip.clone(), port_server);
-        let mut serv_addr : Vec<(String, u16)> = Vec::new();
-        for i in 1..nb_process+1
-        {
-            let (ip, port_server, _) = read_server_address(&hash_net_config, i);
-            serv_addr.push((ip, port_server));
-        }
-
         Process
         {
             id,                                     //arg
-            rec : List::new(),                      //empty
             deps : HashMap::new(),                  //empty
             to_validate : MessageSet::new(),        //empty
-            ongoing_transfer : HashMap::new(),      //empty
-            public_keys : Vec::new(),               //empty
-            serv_addr,                              //loaded
+            to_validate_changed : false,            //empty
+            ongoing_transfers : HashMap::new(),     //empty
+            public_keys,                            //arg
+            weights,                                //arg
+            broadcast_kind,                         //arg
+            transport,                              //arg
             secret_key,                             //arg
-            client_socket,                          //loaded
-            server_socket,                          //loaded
             nb_process,                             //arg
+            data_directory,                         //arg
+            behaviour,                              //arg
+            held_init_messages : HashMap::new(),    //empty
         }
     }
 
     /// The function that allows processes to transfer money
-    pub fn transfer(& mut self,transfer : Transfer, signature : Vec<u8>) -> (bool,u8)
+    pub fn transfer(& mut self,transaction : Transaction, signature : Vec<u8>) -> (bool,u8)
     {
-        if ! transfer.verif_signature_transfer(transfer.sender.id,signature)
+        let message = match self.init_message(transaction, signature)
         {
-            log!("Transaction refused because signature could not be verified!");
-            return (false,1)
-        }
+            Ok(message) => { message }
+            Err(code) => { return (false,code) }
+        };
+        let user_id = message.transaction.sender_id;
 
-        let user_id = transfer.sender;
-        let receiver_id = transfer.recipient;
-        let amount = transfer.amount;
+        // The transaction is in flight until it is validated
+        self.ongoing_transfers.entry(user_id).or_default().push(message.transaction);
 
-        // check if it has enough money or if it does not already have a transfer in progress
-        let sender_money = self.read(user_id);
-        if sender_money < amount
+        // Then the message is signed, and broadcast between all processes. With a gossip broadcast, the
+        // process only gives it to itself, and its broadcast instance relays it to a sample of the processes.
+        // With a dispersal broadcast, its broadcast instance sends each process its own fragment
+        let message = message.sign(&self.secret_key);
+        match self.broadcast_kind
         {
-            log!("The transaction sender does not have enough money to make the transaction. Transaction is
-                refused and not broadcast to others (Sender has {} encoins)", sender_money);
-            return (false,2)
+            BroadcastKind::Gossip(_) | BroadcastKind::Dispersal => { self.transport.send(self.id, message); }
+            _ => { broadcast(self.transport.as_ref(),  message); }
         }
+        (true,0)
+    }
 
-        //check if there is no transaction with the user in progress
-        if *self.ongoing_transfer.entry(user_id.clone()).or_insert(false) == true
+    /// Checks that a transaction signed by the owner of the sending account can be made and returns the
+    /// Init message starting its broadcast. Otherwise returns the code explaining why it is refused
+    pub fn init_message(& mut self,transaction : Transaction, signature : Vec<u8>) -> Result<Message,u8>
+    {
+        let signing = match transfer_signing(&transaction, &signature)
         {
-            log!("Transaction refused for other reasons");
-            return (false,3)
-        }
+            Some(signing) => { signing }
+            None =>
+            {
+                log!("Transaction refused because signature could not be verified!");
+                return Err(1)
+            }
+        };
+
+        let user_id = transaction.sender_id;
 
-        // Then a transaction is created in accordance to the white paper
-        let transaction = Transaction
+        // check that the sequence number chosen by the client follows the validated transactions of the
+        // account, within the window of the transactions which can be in flight
+        let validated_seq = match load_seq(&self.data_directory, &user_id)
         {
-            seq_id: match load_seq(&transfer.sender)
+            Ok(seq_id) => { seq_id }
+            Err(err) =>
             {
-                Ok(num) =>
-                {
-                    num+1
-                }
-                Err(err) =>
-                {
-                    crash_with!("Could not process transaction : {}", err);
-                }
-            },
-            sender_id: user_id.clone(),
-            receiver_id,
-            amount,
+                log!("Transaction refused because the seq file of {} could not be read : {}", user_id, err);
+                return Err(3)
+            }
         };
+        if transaction.seq_id <= validated_seq || transaction.seq_id > validated_seq + TRANSFER_WINDOW
+        {
+            log!("Transaction {} refused because the last validated transaction of the account is {}", transaction, validated_seq);
+            return Err(3)
+        }
 
-        // Which is encapsulated in an Init Message
-        let message  = Message
+        // check that there is room for another transaction of the account in flight
+        let in_flight = self.ongoing_transfers.get(&user_id).map_or(&[][..], |transfers| &transfers[..]);
+        if in_flight.len() as SeqId >= TRANSFER_WINDOW || in_flight.iter().any(|t| t.seq_id == transaction.seq_id)
         {
-            transaction,
-            dependencies: self.deps.entry(user_id.clone()).or_insert(TransferSet::new()).clone(),
-            message_type: MessageType::Init,
-            sender_id: self.id,
-        };
+            log!("Transaction {} refused because {} transactions of the account are already in progress", transaction, in_flight.len());
+            return Err(3)
+        }
 
-        // Then the message is signed, and broadcast between all processes
-        let message = message.sign(&self.secret_key);
-        broadcast(&self.serv_addr,  message);
+        // check that a transfer whose signature does not cover its sequence number is not one of the account
+        // again, which would be refused once the first one is validated
+        if signing == TransferSigning::Legacy
+            && (repeats_transfer(&self.history_for(&user_id), &transaction) || repeats_transfer(in_flight, &transaction))
+        {
+            log!("Transaction {} refused because the account already made the same transfer", transaction);
+            return Err(4)
+        }
 
-        // transfers are now blocked
-        *self.ongoing_transfer.entry(user_id.clone()).or_insert(true) = true;
-        (true,0)
+        // check if it has enough money, once the transactions in progress are validated
+        let in_flight_amount = in_flight.iter().fold(0 as Currency, |sum, transaction| sum.saturating_add(transaction.amount));
+        let sender_money = self.read(user_id).saturating_sub(in_flight_amount);
+        if sender_money < transaction.amount
+        {
+            log!("The transaction sender does not have enough money to make the transaction. Transaction is
+                refused and not broadcast to others (Sender has {} encoins)", sender_money);
+            return Err(2)
+        }
+
+        // Then the transaction is encapsulated in an Init Message
+        let dependencies = self.deps.entry(user_id).or_default().clone();
+        Ok(Message::new(transaction, dependencies, MessageType::Init, self.id, self.id, signature))
+    }
+
+    /// Returns the transaction of a [`Transfer`] of the encoins API, whose signature does not cover the
+    /// sequence number. The transaction follows the last one of the account validated or in flight, or
+    /// the code explaining why there is none
+    pub fn legacy_transaction(&self, transfer : Transfer) -> Result<Transaction,u8>
+    {
+        let user_id = transfer.sender;
+        let validated_seq = match load_seq(&self.data_directory, &user_id)
+        {
+            Ok(seq_id) => { seq_id }
+            Err(err) =>
+            {
+                log!("Transfer refused because the seq file of {} could not be read : {}", user_id, err);
+                return Err(3)
+            }
+        };
+        let in_flight = self.ongoing_transfers.get(&user_id).map_or(&[][..], |transfers| &transfers[..]);
+        let seq_id = in_flight.iter().map(|transaction| transaction.seq_id).fold(validated_seq, std::cmp::max) + 1;
+        Ok(Transaction::from(seq_id, user_id, transfer.recipient, transfer.amount))
+    }
+
+    /// Releases the transfers of an account the process broadcast from sequence number `seq_id` on,
+    /// whose broadcast was given up. The ones after `seq_id` can not be validated without it
+    pub fn release_transfers(&mut self, account : &UserId, seq_id : SeqId)
+    {
+        if let Some(in_flight) = self.ongoing_transfers.get_mut(account)
+        {
+            let nb_in_flight = in_flight.len();
+            in_flight.retain(|transaction| transaction.seq_id < seq_id);
+            if in_flight.len() < nb_in_flight
+            {
+                log!("Released {} transfers of {} from transaction {} on", nb_in_flight - in_flight.len(), account, seq_id);
+            }
+        }
     }
 
     /// The function that returns the balance of money owned by the process
     pub fn read(&self,user : UserId) -> Currency
     {
-        return Process::balance(user, &self.history_for(&user))
+        Process::balance(user, &self.history_for(&user))
     }
 
     /// Function that given a set of transfer and an ID returns the balance of money earned by the process a
@@ -158,45 +221,69 @@ impl Process
     fn balance( a: UserId, h: &TransferSet) -> Currency
     {
 
-        let mut balance : u32 = 0;
+        let mut incomes : Currency = 0;
+        let mut expenses : Currency = 0;
         for transfer in h
         {
             if transfer.receiver_id == a
             {
-                balance += transfer.amount;
+                incomes = incomes.saturating_add(transfer.amount);
             }
             if transfer.sender_id == a
             {
-                balance -= transfer.amount;
+                expenses = expenses.saturating_add(transfer.amount);
             }
         }
-        balance
+        incomes.saturating_sub(expenses)
     }
 
-    /// function which tests the validity of every messages pending validation according to the white paper
+    /// function which tests the validity of every messages pending validation according to the white paper.
+    /// Does nothing if no transfer was delivered since the last call
     pub fn valid(&mut self)
     {
-        let mut index = 0;
-        loop
+        if !self.to_validate_changed
         {
-            let message = match self.to_validate.get(index)
-            {
-                Some(message) => {message}
-                None => break
-            };
-            if self.is_valid( message)
-            {
-                // Save transaction for receiver and sender
-                write_transaction(&message.transaction);
-                *self.ongoing_transfer.entry(message.clone().transaction.sender_id).or_insert(false) = false;
-                log!("Transaction {} is valid and confirmed on my part.", message.transaction);
-                self.to_validate.remove(index);
-            }
-            else
+            return;
+        }
+        self.to_validate_changed = false;
+
+        // Validating a transfer can make another one valid, so loop until nothing changes
+        let mut validated = true;
+        while validated
+        {
+            validated = false;
+            let mut index = 0;
+            while let Some((message, certificate)) = self.to_validate.get(index)
             {
-                index += 1;
-                log!("Transaction {} is not (or still not) valid and is refused on my part.",
-                    message.transaction);
+                if self.is_valid( message)
+                {
+                    // Save transaction for receiver and sender, and the proof of its delivery
+                    write_transaction(&self.data_directory, &message.transaction);
+                    write_certificate(&self.data_directory, certificate);
+                    let sender_id = message.transaction.sender_id;
+                    let seq_id = message.transaction.seq_id;
+                    if let Some(in_flight) = self.ongoing_transfers.get_mut(&sender_id)
+                    {
+                        in_flight.retain(|transaction| transaction.seq_id != seq_id);
+                    }
+                    // The incoming transfers the sender reported are now accounted for, and the transfer
+                    // becomes a dependency of the next transfer of the receiver
+                    if let Some(deps) = self.deps.get_mut(&sender_id)
+                    {
+                        deps.retain(|transaction| !message.dependencies.contains(transaction));
+                    }
+                    let transaction = message.transaction;
+                    self.deps.entry(transaction.receiver_id).or_default().push(transaction);
+                    log!("Transaction {} is valid and confirmed on my part.", message.transaction);
+                    self.to_validate.remove(index);
+                    validated = true;
+                }
+                else
+                {
+                    index += 1;
+                    log!("Transaction {} is not (or still not) valid and is refused on my part.",
+                        message.transaction);
+                }
             }
         }
     }
@@ -204,13 +291,17 @@ impl Process
     /// Function that tests if a message is validated by the process
     fn is_valid(&self, message : &Message) -> bool
     {
-        // 1) process q (the issuer of transfer op) must be the owner of the outgoing
-        let assert1 = true; // verified in deal_with_message for init messages
         // 2) any preceding transfers that process q issued must have been validated
-        let assert2 = message.transaction.seq_id ==  load_seq(&message.transaction.sender_id)
-            .expect("Something got wrong with the loading of a seq file") +1 ;
-        // 3) the balance of account q must not drop below zero
-        let history = match load_history(&message.transaction.sender_id)
+        let assert2 = match load_seq(&self.data_directory, &message.transaction.sender_id)
+        {
+            Ok(seq_id) => { message.transaction.seq_id == seq_id + 1 }
+            Err(err) =>
+            {
+                log!("Could not load seq file for user {} (Error: {}).", &message.transaction.sender_id, err);
+                return false
+            }
+        };
+        let history = match load_history(&self.data_directory, &message.transaction.sender_id)
         {
             Ok(h) =>
             {
@@ -218,23 +309,24 @@ impl Process
             }
             Err(err) =>
             {
-                crash_with!("Could not load history for user {} (Error: {}).",
+                log!("Could not load history for user {} (Error: {}).",
                     &message.transaction.sender_id, err);
+                return false
             }
         };
+        // 1) process q (the issuer of transfer op) must be the owner of the outgoing. A signature which does
+        // not cover the sequence number could be replayed, so it must not be the one of an earlier transfer
+        let assert1 = match message.transfer_signing()
+        {
+            Some(TransferSigning::Transaction) => { true }
+            Some(TransferSigning::Legacy) => { !repeats_transfer(&history, &message.transaction) }
+            None => { false }
+        };
+        // 3) the balance of account q must not drop below zero
         let assert3 = Process::balance(message.clone().transaction.sender_id, &history) >= message.transaction.amount;
         // 4) the reported dependencies of op (encoded in h of line 26) must have been validated and exist in hist[q]
-        let mut assert4 = true;
-        for dependence in &message.dependencies
-        {
-            if self.deps.get(&message.transaction.sender_id)
-                .expect("In Hash table deps, no corresponding entry for the sender of a transaction in process")
-                .clone().iter().any(|transaction| transaction == dependence)
-            {
-                //return false;
-                assert4 = false;
-            }
-        }
+        let assert4 = message.dependencies.iter()
+            .all(|dependence| dependence.receiver_id == message.transaction.sender_id && history.contains(dependence));
 
         log!("proc {} a {} {} {} {}",self.id,assert1,assert2,assert3,assert4);
 
@@ -244,16 +336,16 @@ impl Process
     /// Returns the history of a given account according to the process
     fn history_for(&self, account: &UserId) -> TransferSet
     {
-        match load_history(account)
+        match load_history(&self.data_directory, account)
         {
             Ok(his) =>
             {
-                return his
+                his
             }
             Err(err) =>
             {
                 log!("Could not load history for account {}. (Error: {}). This should not happen!",account, err);
-                return vec![];
+                vec![]
             }
         }
     }
@@ -263,7 +355,7 @@ impl Process
     {
         let mut positive_balance = 0;
         let mut negative_balance = 0;
-        match load_history(&account)
+        match load_history(&self.data_directory, &account)
         {
 
             Ok(hist) =>
@@ -292,7 +384,8 @@ impl Process
 
             Err(err) =>
             {
-                crash_with!("Could not load history for user ! (Error : {})", err);
+                log!("Could not load history for user {} ! (Error : {})", account, err);
+                0
             }
         }
     }
@@ -300,26 +393,189 @@ impl Process
 
     pub fn get_key_pair(&self) -> &Keypair
     {
-        return &self.secret_key
+        &self.secret_key
+    }
+
+    /// Returns the public key of process `id`, if it exists
+    pub fn get_public_key(&self, id : ProcId) -> Option<&PublicKey>
+    {
+        match id
+        {
+            0 => { None }
+            _ => { self.public_keys.get(id as usize - 1) }
+        }
+    }
+
+    /// Returns the public keys of the processes, the i-th one being the one of process i+1
+    pub fn get_public_keys(&self) -> &[PublicKey]
+    {
+        &self.public_keys
+    }
+
+    pub fn get_weights(&self) -> &Weights
+    {
+        &self.weights
+    }
+
+    pub fn get_broadcast_kind(&self) -> BroadcastKind
+    {
+        self.broadcast_kind
+    }
+
+    pub fn get_data_directory(&self) -> &str
+    {
+        &self.data_directory
+    }
+
+    pub fn get_behaviour(&self) -> Behaviour
+    {
+        self.behaviour
+    }
+
+    pub fn set_behaviour(&mut self, behaviour : Behaviour)
+    {
+        self.behaviour = behaviour;
+    }
+
+    pub fn get_held_init_messages(&mut self) -> &mut HashMap<UserId,Message>
+    {
+        &mut self.held_init_messages
     }
 
-    pub fn get_client_socket(&self) -> (String, u16)
+    pub fn get_transport(&self) -> &dyn Transport
     {
-        self.client_socket.clone()
+        self.transport.as_ref()
     }
 
-    pub fn get_server_socket(&self) -> (String, u16)
+    pub fn in_to_validate(&mut self, message : Message, certificate : DeliveryCertificate)
     {
-        self.server_socket.clone()
+        self.to_validate.push((message, certificate));
+        self.to_validate_changed = true;
     }
+}
+
+/// States whether some transaction of a set sends the same amount from the same account to the same account as
+/// the given one, in which case their signatures are the same if they do not cover the sequence number
+fn repeats_transfer(transactions : &[Transaction], transaction : &Transaction) -> bool
+{
+    transactions.iter().any(|other| other.sender_id == transaction.sender_id && other.receiver_id == transaction.receiver_id
+        && other.amount == transaction.amount)
+}
 
-    pub fn get_serv_addr(&self) -> &Vec<(String, u16)>
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::time::Duration;
+    use crate::broadcast::{BroadcastId, OngoingBroadcasts};
+    use crate::crypto::sign_transaction;
+    use ed25519_dalek::Signer;
+    use crate::messaging;
+    use crate::test_utils;
+
+    #[test]
+    fn transfers_not_signed_by_the_owner_are_neither_echoed_nor_validated()
     {
-        &(self.serv_addr)
+        let mut network = test_utils::network("unsigned_transfers", 4, BroadcastKind::Reliable);
+        let (account, keypair) = test_utils::owner(1);
+        test_utils::fund(&network, account, 100);
+        let transaction = Transaction::from(1, account, test_utils::account(), 10);
+        let signature = sign_transaction(&transaction, &keypair);
+
+        // Process 1 starts the broadcast of a transaction with the signature of another one
+        let forged = Message::new(Transaction::from(1, account, test_utils::account(), 90), vec![], MessageType::Init,
+            1, 1, signature.clone());
+        let genuine = Message::new(transaction, vec![], MessageType::Init, 1, 1, signature);
+        let signed_forged = forged.clone().sign(network[0].0.get_key_pair());
+        let signed_genuine = genuine.clone().sign(network[0].0.get_key_pair());
+
+        let mut ongoing_broadcasts = OngoingBroadcasts::new(Duration::from_secs(30));
+        let process = &mut network[1].0;
+        messaging::deal_with_message(process, signed_forged, &mut ongoing_broadcasts);
+        assert!(!ongoing_broadcasts.contains_key(&BroadcastId::of(&forged)));
+        assert!(network.iter().all(|(_, inbox)| inbox.try_recv().is_err()));
+        assert!(!network[1].0.is_valid(&forged));
+
+        let process = &mut network[1].0;
+        messaging::deal_with_message(process, signed_genuine, &mut ongoing_broadcasts);
+        assert!(ongoing_broadcasts.contains_key(&BroadcastId::of(&genuine)));
+        assert!(network.iter().all(|(_, inbox)| inbox.try_recv().is_ok()));
+        assert!(network[1].0.is_valid(&genuine));
     }
 
-    pub fn in_to_validate(&mut self, message : Message)
+    #[test]
+    fn transfers_signed_without_their_sequence_number_are_validated_once()
     {
-        self.to_validate.push(message);
+        let mut network = test_utils::network("legacy_transfers", 4, BroadcastKind::Reliable);
+        let (account, keypair) = test_utils::owner(1);
+        test_utils::fund(&network, account, 100);
+        let transfer = Transfer { sender : account, recipient : test_utils::account(), amount : 10 };
+        let signature = keypair.sign(&bincode::serialize(&transfer).unwrap()).to_bytes().to_vec();
+        assert!(transfer.verif_signature_transfer(account.id, signature.clone()));
+
+        // The process gives the transfer the next sequence number, but refuses the same transfer again
+        let process = &mut network[0].0;
+        let transaction = process.legacy_transaction(transfer.clone()).unwrap();
+        assert_eq!(transaction.seq_id, 1);
+        assert_eq!(process.transfer(transaction, signature.clone()), (true, 0));
+        let again = process.legacy_transaction(transfer).unwrap();
+        assert_eq!(again.seq_id, 2);
+        assert_eq!(process.transfer(again, signature.clone()), (false, 4));
+
+        // Once the transfer is validated, its signature can not be replayed with another sequence number
+        let message = Message::new(transaction, vec![], MessageType::Init, 1, 1, signature.clone());
+        assert_eq!(message.transfer_signing(), Some(TransferSigning::Legacy));
+        let certificate = DeliveryCertificate::from(&message, MessageType::Ready, vec![]);
+        process.in_to_validate(message, certificate);
+        process.valid();
+        assert_eq!(load_seq(process.get_data_directory(), &account).unwrap(), 1);
+        let replay = Message::new(again, vec![], MessageType::Init, 2, 2, signature);
+        assert!(replay.verif_transfer_signature());
+        assert!(!process.is_valid(&replay));
+    }
+
+    #[test]
+    fn transfers_in_flight_are_validated_in_sequence_order()
+    {
+        let mut network = test_utils::network("pipelined_transfers", 4, BroadcastKind::Reliable);
+        let (account, keypair) = test_utils::owner(1);
+        test_utils::fund(&network, account, 100);
+        let signed : Vec<(Transaction, Vec<u8>)> = (1..=TRANSFER_WINDOW + 1)
+            .map(|seq_id| Transaction::from(seq_id, account, test_utils::account(), 10))
+            .map(|transaction| (transaction, sign_transaction(&transaction, &keypair)))
+            .collect();
+        let window = TRANSFER_WINDOW as usize;
+
+        // The transactions of the window can be in flight at the same time, whatever the order they are asked in
+        let process = &mut network[0].0;
+        for (transaction, signature) in signed[..window].iter().rev()
+        {
+            assert_eq!(process.transfer(*transaction, signature.clone()), (true, 0));
+        }
+        let (next, next_signature) = &signed[window];
+        assert_eq!(process.transfer(*next, next_signature.clone()), (false, 3));
+
+        // They are validated in sequence order, whatever the order they are delivered in
+        let deliver = |process : &mut Process, seq_id : SeqId|
+        {
+            let (transaction, signature) = &signed[seq_id as usize - 1];
+            let message = Message::new(*transaction, vec![], MessageType::Init, 1, 1, signature.clone());
+            let certificate = DeliveryCertificate::from(&message, MessageType::Ready, vec![]);
+            process.in_to_validate(message, certificate);
+            process.valid();
+            load_seq(process.get_data_directory(), &account).unwrap()
+        };
+        assert_eq!(deliver(process, 2), 0);
+        assert_eq!(deliver(process, 1), 2);
+        assert_eq!(deliver(process, 4), 2);
+        assert_eq!(deliver(process, 3), 4);
+        let sent : Vec<SeqId> = load_history(process.get_data_directory(), &account).unwrap().iter()
+            .filter(|transaction| transaction.sender_id == account)
+            .map(|transaction| transaction.seq_id)
+            .collect();
+        assert_eq!(sent, (1..=TRANSFER_WINDOW).collect::<Vec<SeqId>>());
+
+        // Validated transactions leave room for the next ones
+        assert_eq!(process.transfer(*next, next_signature.clone()), (true, 0));
     }
 }
@@ -0,0 +1,267 @@
+//! Encrypted and mutually authenticated channels between servers
+//!
+//! A channel is established on top of a [`TcpStream`] in two steps:
+//! - a Noise `NN` handshake agrees on ephemeral keys used to encrypt everything that follows ;
+//! - both servers then send their id, their ed25519 public key and a signature of the handshake
+//!   hash with their identity key. The signature binds the identity of the server to this very
+//!   channel, so a server which does not own the identity key can not impersonate it. The public
+//!   key must also be the one declared for the server in the configuration of the network.
+//!
+//! Once established, payloads are sent as frames (see [`crate::framing`]) which are encrypted by
+//! chunks, each chunk being itself sent as a frame.
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use ed25519_dalek::{Keypair, PublicKey, Signer};
+use serde::{Serialize, Deserialize};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use crate::crypto::verify_signature;
+use crate::errors::decode;
+use crate::framing::{encode_frame, write_frame, FrameDecoder};
+use crate::process::ProcId;
+
+/// Noise protocol used for the channels
+const NOISE_PARAMS: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+/// Maximal size of a Noise message
+const MAX_NOISE_MESSAGE: usize = 65535;
+/// Size of the authentication tag added to each encrypted chunk
+const TAG_SIZE: usize = 16;
+/// Maximal size of the plaintext of an encrypted chunk
+const MAX_CHUNK_SIZE: usize = MAX_NOISE_MESSAGE - TAG_SIZE;
+/// Time allowed to the other side to complete the establishment of the channel
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Context signed by the server opening the channel
+const INITIATOR_CONTEXT: &[u8] = b"encoins channel initiator";
+/// Context signed by the server accepting the channel
+const RESPONDER_CONTEXT: &[u8] = b"encoins channel responder";
+
+/// Keys used to authenticate the channels of the local server
+#[derive(Debug)]
+pub struct ChannelKeys
+{
+    /// Id of the local server
+    id: ProcId,
+    /// Identity keypair of the local server
+    keypair: Keypair,
+    /// Public keys of the servers of the network, as declared in the configuration
+    peer_keys: HashMap<ProcId, PublicKey>,
+}
+
+/// Identity sent by each side once the handshake is done
+#[derive(Serialize, Deserialize)]
+struct ChannelIdentity
+{
+    id: ProcId,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+/// Writing half of an established channel
+pub struct SecureWriter
+{
+    stream: TcpStream,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+/// Reading half of an established channel
+pub struct SecureReader
+{
+    stream: TcpStream,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+    /// Reassembles the encrypted chunks
+    chunks: FrameDecoder,
+    /// Reassembles the frames from the decrypted chunks
+    frames: FrameDecoder,
+}
+
+impl ChannelKeys
+{
+    pub fn new(id: ProcId, keypair: Keypair, peer_keys: HashMap<ProcId, PublicKey>) -> ChannelKeys
+    {
+        ChannelKeys
+        {
+            id,
+            keypair,
+            peer_keys,
+        }
+    }
+
+    /// Checks that the given public key is the one declared for server `id`
+    fn authenticate(&self, id: ProcId, public_key: PublicKey) -> bool
+    {
+        self.peer_keys.get(&id) == Some(&public_key)
+    }
+}
+
+/// Opens a channel on a stream connected to server `expected_id`
+pub fn connect(stream: TcpStream, keys: &ChannelKeys, expected_id: ProcId) -> Result<(SecureWriter, SecureReader), Error>
+{
+    let handshake = noise_builder().build_initiator().map_err(noise_error)?;
+    let (writer, reader, _) = establish(stream, keys, handshake, Some(expected_id))?;
+    Ok((writer, reader))
+}
+
+/// Accepts a channel opened by another server on the stream.
+/// Returns the two halves of the channel and the id of the authenticated server
+pub fn accept(stream: TcpStream, keys: &ChannelKeys) -> Result<(SecureWriter, SecureReader, ProcId), Error>
+{
+    let handshake = noise_builder().build_responder().map_err(noise_error)?;
+    establish(stream, keys, handshake, None)
+}
+
+fn noise_builder<'a>() -> Builder<'a>
+{
+    Builder::new(NOISE_PARAMS.parse().expect("Invalid Noise protocol name"))
+}
+
+fn noise_error(error: snow::Error) -> Error
+{
+    Error::new(ErrorKind::InvalidData, format!("Noise error : {}", error))
+}
+
+/// Runs the handshake and the authentication of both servers
+fn establish(stream: TcpStream, keys: &ChannelKeys, mut handshake: HandshakeState, expected_id: Option<ProcId>)
+    -> Result<(SecureWriter, SecureReader, ProcId), Error>
+{
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut write_stream = stream.try_clone()?;
+    let mut read_stream = stream;
+    let mut decoder = FrameDecoder::new();
+    let buf = &mut [0; MAX_NOISE_MESSAGE];
+
+    // Noise handshake: -> e, <- e, ee
+    while !handshake.is_handshake_finished()
+    {
+        if handshake.is_my_turn()
+        {
+            let len = handshake.write_message(&[], buf).map_err(noise_error)?;
+            write_frame(&mut write_stream, &buf[..len])?;
+        }
+        else
+        {
+            let frame = match decoder.read_frame(&mut read_stream)?
+            {
+                Some(frame) => { frame }
+                None => { return Err(Error::new(ErrorKind::UnexpectedEof, "Stream closed during the handshake")); }
+            };
+            handshake.read_message(&frame, buf).map_err(noise_error)?;
+        }
+    }
+
+    let (own_context, peer_context) = match handshake.is_initiator()
+    {
+        true => { (INITIATOR_CONTEXT, RESPONDER_CONTEXT) }
+        false => { (RESPONDER_CONTEXT, INITIATOR_CONTEXT) }
+    };
+    let handshake_hash = handshake.get_handshake_hash().to_vec();
+    let transport = Arc::new(handshake.into_stateless_transport_mode().map_err(noise_error)?);
+
+    let mut writer = SecureWriter
+    {
+        stream: write_stream,
+        transport: transport.clone(),
+        nonce: 0,
+    };
+    let mut reader = SecureReader
+    {
+        stream: read_stream,
+        transport,
+        nonce: 0,
+        chunks: decoder,
+        frames: FrameDecoder::new(),
+    };
+
+    // Authentication: each side signs the handshake hash with its identity key
+    let identity = ChannelIdentity
+    {
+        id: keys.id,
+        public_key: keys.keypair.public.to_bytes().to_vec(),
+        signature: keys.keypair.sign(&[own_context, &handshake_hash[..]].concat()).to_bytes().to_vec(),
+    };
+    writer.send(&bincode::serialize(&identity).expect("Problem with the serialization of an identity"))?;
+
+    let peer_identity : ChannelIdentity = match reader.receive()?
+    {
+        Some(frame) => { decode(&frame).map_err(|e| Error::new(ErrorKind::InvalidData, e))? }
+        None => { return Err(Error::new(ErrorKind::UnexpectedEof, "Stream closed during the authentication")); }
+    };
+
+    if let Some(expected_id) = expected_id
+    {
+        if peer_identity.id != expected_id
+        {
+            return Err(Error::new(ErrorKind::PermissionDenied,
+                format!("Expected server {} but server {} answered", expected_id, peer_identity.id)));
+        }
+    }
+
+    let public_key = PublicKey::from_bytes(&peer_identity.public_key)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if verify_signature(&public_key, &[peer_context, &handshake_hash[..]].concat(), &peer_identity.signature).is_err()
+    {
+        return Err(Error::new(ErrorKind::PermissionDenied,
+            format!("Server {} could not prove it owns its identity key", peer_identity.id)));
+    }
+    if !keys.authenticate(peer_identity.id, public_key)
+    {
+        return Err(Error::new(ErrorKind::PermissionDenied,
+            format!("Server {} presented an identity key which is not the one of its configuration", peer_identity.id)));
+    }
+
+    reader.stream.set_read_timeout(None)?;
+    Ok((writer, reader, peer_identity.id))
+}
+
+impl SecureWriter
+{
+    /// Encrypts and sends the given payload as a single frame
+    pub fn send(&mut self, payload: &[u8]) -> Result<(), Error>
+    {
+        let plaintext = encode_frame(payload)?;
+        let buf = &mut [0; MAX_NOISE_MESSAGE];
+        for chunk in plaintext.chunks(MAX_CHUNK_SIZE)
+        {
+            let len = self.transport.write_message(self.nonce, chunk, buf).map_err(noise_error)?;
+            self.nonce += 1;
+            write_frame(&mut self.stream, &buf[..len])?;
+        }
+        Ok(())
+    }
+
+    /// Closes both halves of the channel
+    pub fn shutdown(&self)
+    {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl SecureReader
+{
+    /// Receives and decrypts the next frame.
+    /// Returns `Ok(None)` if the stream was closed by the other side
+    pub fn receive(&mut self) -> Result<Option<Vec<u8>>, Error>
+    {
+        let buf = &mut [0; MAX_NOISE_MESSAGE];
+        loop
+        {
+            if let Some(frame) = self.frames.next_frame()?
+            {
+                return Ok(Some(frame));
+            }
+
+            let chunk = match self.chunks.read_frame(&mut self.stream)?
+            {
+                Some(chunk) => { chunk }
+                None => { return Ok(None); }
+            };
+            let len = self.transport.read_message(self.nonce, &chunk, buf).map_err(noise_error)?;
+            self.nonce += 1;
+            self.frames.push(&buf[..len]);
+        }
+    }
+}
@@ -1,12 +1,16 @@
 use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
-use std::io::{Read, Write};
 use std::thread;
-use bincode::deserialize;
-use crate::{log, SignedMessage};
+use crate::errors::decode;
+use crate::crypto::SignedMessage;
+use crate::log;
+use crate::secure_channel;
+use crate::secure_channel::ChannelKeys;
+use crate::event::Event;
 
 /// management of the stream received with the socket
-pub fn server_listener(socket : (String, u16), msgsender : Sender<SignedMessage>) 
+pub fn server_listener(socket : (String, u16), msgsender : Sender<Event>, keys : Arc<ChannelKeys>) 
 {
     let listener = TcpListener::bind(socket)
         .expect("Problem with the binding to the server socket");
@@ -26,7 +30,8 @@ pub fn server_listener(socket : (String, u16), msgsender : Sender<SignedMessage>
                 
                 //handling the stream in a new thread
                 let msgsender_copy = msgsender.clone();
-                thread::spawn( move || {handle_server(stream, &*adresse, msgsender_copy);});
+                let keys_copy = keys.clone();
+                thread::spawn( move || {handle_server(stream, &adresse, msgsender_copy, &keys_copy);});
             }
             Err(e) => 
             {
@@ -36,53 +41,69 @@ pub fn server_listener(socket : (String, u16), msgsender : Sender<SignedMessage>
     }
 }
 
-/// retransmit the content of stream with sender
-fn handle_server(mut stream: TcpStream, adresse: &str, sender: Sender<SignedMessage>) 
+/// retransmit the content of stream with sender, once the server at the other end is authenticated.
+/// Messages claiming to come from another server than the authenticated one are dropped
+fn handle_server(stream: TcpStream, adresse: &str, sender: Sender<Event>, keys: &ChannelKeys) 
 {
-    loop 
+    let (mut writer, mut reader, peer_id) = match secure_channel::accept(stream, keys)
     {
-        let buf = &mut [0; 200];
+        Ok(channel) => { channel }
+        Err(e) =>
+        {
+            log!("Could not establish a secure channel with {} : {}", adresse, e);
+            return;
+        }
+    };
+    log!("Server {} authenticated {}", peer_id, adresse);
 
-        match stream.read(buf) 
+    // Number of messages received on this connection, sent back as an acknowledgement
+    let mut received : u64 = 0;
+    loop 
+    {
+        match reader.receive() 
         {
-            Ok(received) => 
+            Ok(Some(frame)) => 
             {
-                if received < 1 
+                //send the msg with sender
+                log!("Frame of {} bytes from serv {:?}", frame.len(), adresse);
+                received += 1;
+                let msg : SignedMessage = match decode(&frame[..])
                 {
-                    log!("Server disconnected {}", adresse);
-                    return;
+                    Ok(msg) => { msg }
+                    Err(e) =>
+                    {
+                        log!("Could not deserialize a message from server {} : {}", adresse, e);
+                        continue;
+                    }
+                };
+                if msg.message.sender_id != peer_id
+                {
+                    log!("Server {} sent a message on behalf of server {}, dropping it", peer_id, msg.message.sender_id);
+                }
+                else
+                {
+                    sender.send(Event::Message(msg))
+                        .expect("the channel between the main thread and the server thread is closed");
                 }
 
-                //send the msg with sender
-                log!("buff from serv{:?}", adresse);
-                let msg : SignedMessage = deserialize(&buf[..])
-                    .expect("Problem with the deserialization of a server message");
-                sender.send(msg)
-                    .expect("the channel between the main thread and the server thread is closed");
+                let ack = &(bincode::serialize(&received)
+                    .expect("Problem with the serialization of an acknowledgement")[..]);
+                if let Err(e) = writer.send(ack)
+                {
+                    log!("Server disconnected {} : {}", adresse, e);
+                    return;
+                }
             }
-            Err(_) => 
+            Ok(None) =>
             {
                 log!("Server disconnected {}", adresse);
                 return;
             }
+            Err(e) => 
+            {
+                log!("Server disconnected {} : {}", adresse, e);
+                return;
+            }
         }
     }
 }
-
-/// send message to the IP address
-pub fn send(addr : &(String, u16), message : SignedMessage ) {
-    match TcpStream::connect(addr) 
-    {
-        Ok(mut stream) => 
-        {
-            let serialized_msg = &(bincode::serialize(&message)
-                .expect("Problem with the deserialization of a message before sending phase")[..]);
-            stream.write(serialized_msg)
-                .expect("A message cannot be sent because of a bad stream");
-        }
-        Err(e) => 
-        {
-            log!("Connexion to server failed : {}", e);
-        }
-    }
-}
\ No newline at end of file
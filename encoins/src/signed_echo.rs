@@ -0,0 +1,151 @@
+//! Signed echo broadcast, a consistent broadcast (see *Introduction to Reliable and Secure Distributed
+//! Programming*, Cachin, Guerraoui and Rodrigues, section 3.10)
+//!
+//! The origin of the broadcast sends its Init message to every server, and each server answers it with a
+//! signed Echo message sent to the origin only. Once the Echo messages the origin gathered for the same
+//! message weigh at least `⌈(n+f+1)/2⌉`, it sends them to every server in a Final message, and a server
+//! delivers the message of a Final carrying enough valid signatures. A broadcast thus costs `O(n)`
+//! messages instead of the `O(n²)` of [`crate::broadcast::Broadcast`].
+//!
+//! # Properties
+//!
+//! When at most `f` process are byzantine, the broadcast ensures validity, no duplication, integrity and
+//! consistency, since two quorums of Echo messages share a correct process, which echoes a single message.
+//! It does not ensure totality: a byzantine origin can send its Final message to some servers only.
+
+use std::collections::HashMap;
+use std::time::Instant;
+use crate::broadcast::{Action, BroadcastInstance, Weight, Weights};
+use crate::certificate::DeliveryCertificate;
+use crate::log;
+use crate::message::{Message, MessageType};
+use crate::process::ProcId;
+
+/// A signed echo broadcast a process takes part to
+pub struct SignedEchoBroadcast
+{
+    /// Id of the process taking part to the broadcast
+    proc_id : ProcId,
+    /// Id of the process which started the broadcast, to which Echo messages are sent
+    origin : ProcId,
+    /// Weights of the processes involved in the broadcast
+    weights : Weights,
+    /// Echo messages received by the origin, with their signature, by sender
+    echos : HashMap<ProcId, (Message, Vec<u8>)>,
+    /// Variable stating if the origin already sent the Final message
+    final_message_sent : bool,
+    /// Variable stating if the message was already delivered
+    delivered : bool,
+    /// Instant the broadcast started
+    started : Instant,
+}
+
+impl SignedEchoBroadcast
+{
+    pub fn new(proc_id : ProcId, origin : ProcId, weights : Weights) -> SignedEchoBroadcast
+    {
+        SignedEchoBroadcast
+        {
+            proc_id,
+            origin,
+            weights,
+            echos : HashMap::new(),
+            final_message_sent : false,
+            delivered : false,
+            started : Instant::now(),
+        }
+    }
+
+    /// Returns the processes which sent an Echo message for the given message, with their signature
+    fn echo_signers(&self, message : &Message) -> Vec<(ProcId, Vec<u8>)>
+    {
+        let mut signers : Vec<(ProcId, Vec<u8>)> = self.echos.iter()
+            .filter(|(_, (echo, _))| echo == message)
+            .map(|(id, (_, signature))| (*id, signature.clone()))
+            .collect();
+        signers.sort_by_key(|(id, _)| *id);
+        signers
+    }
+}
+
+impl BroadcastInstance for SignedEchoBroadcast
+{
+    fn start(&mut self, init : &Message, _origin_signature : &[u8]) -> Vec<Action>
+    {
+        let mut echo_msg = init.clone();
+        echo_msg.sender_id = self.proc_id;
+        echo_msg.message_type = MessageType::Echo;
+        log!("Sending echo message to origin {}", self.origin);
+        vec![Action::SendTo(self.origin, echo_msg)]
+    }
+
+    fn add_message(&mut self, message : Message, signature : Vec<u8>) -> Vec<Action>
+    {
+        match message.message_type
+        {
+            MessageType::Echo if self.proc_id == self.origin =>
+            {
+                log!("Received an echo message from {}", message.sender_id);
+                if self.weights.of(message.sender_id) == 0 || self.final_message_sent
+                {
+                    return vec![];
+                }
+                self.echos.insert(message.sender_id, (message.clone(), signature));
+
+                let signers = self.echo_signers(&message);
+                let signers_weight : Weight = signers.iter().map(|(id, _)| self.weights.of(*id)).sum();
+                if signers_weight < self.weights.echo_quorum()
+                {
+                    return vec![];
+                }
+
+                log!("Gathered a quorum of echo messages. Broadcasting them to everyone.");
+                self.final_message_sent = true;
+                let mut final_msg = message;
+                final_msg.sender_id = self.proc_id;
+                final_msg.message_type = MessageType::Final;
+                final_msg.echo_signatures = signers;
+                vec![Action::Broadcast(final_msg)]
+            }
+            // The signatures of a Final message are checked before it is given to the broadcast
+            MessageType::Final =>
+            {
+                log!("Received a final message from {}", message.sender_id);
+                if self.delivered
+                {
+                    return vec![];
+                }
+
+                log!("Quorum was achieved. I can add the message to transactions to process.");
+                self.delivered = true;
+                let certificate = DeliveryCertificate::from(&message, MessageType::Echo, message.echo_signatures.clone());
+                vec![Action::Deliver(message, Box::new(certificate))]
+            }
+            message_type =>
+            {
+                log!("Received {} message from {} which is not part of a consistent broadcast",
+                    message_type, message.sender_id);
+                vec![]
+            }
+        }
+    }
+
+    fn started(&self) -> Instant
+    {
+        self.started
+    }
+
+    fn is_delivered(&self) -> bool
+    {
+        self.delivered
+    }
+
+    fn stuck_reason(&self) -> &'static str
+    {
+        match self.proc_id == self.origin && !self.final_message_sent
+        {
+            true => { "not enough echo messages" }
+            false => { "no final message" }
+        }
+    }
+}
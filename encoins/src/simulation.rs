@@ -0,0 +1,669 @@
+//! Deterministic simulation of a network of nodes running in a single program
+//!
+//! The nodes are wired together with a [`MemoryTransport`], but messages are not handed to them
+//! directly: every message sent is kept in flight by a scheduler which, using a seeded random
+//! generator, decides when it is delivered and to whom first. The scheduler can thus delay,
+//! reorder, duplicate and drop messages, and two runs with the same seed are identical.
+//!
+//! Clients are simulated too: every account is given [`INITIAL_BALANCE`] encoins by a genesis transfer
+//! written in the histories of all nodes, then in each round every account asks a random node to
+//! transfer a random amount to another account, and the network runs until no message is in flight
+//! anymore. Amounts can exceed what is left on an account, so that nodes have to refuse some transfers.
+//! Once all rounds are done, the histories of the nodes are checked against the properties of
+//! the Byzantine Reliable Broadcast documented in [`crate::broadcast`], and every validated transaction
+//! must come with a valid [`crate::certificate::DeliveryCertificate`]. The certificates of a gossip
+//! broadcast can not be checked, so they only have to be there.
+//!
+//! The last nodes of the network can be started in a byzantine mode (see [`crate::byzantine`]),
+//! in which case the properties are only checked on the histories of the correct nodes. The clients
+//! of equivocating nodes collude with them, and sign a second transaction with the same sequence number.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::remove_dir_all;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+use ed25519_dalek::{Keypair, PublicKey};
+use encoins_api::base_types::{Currency, SeqId, Transaction, UserId};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::broadcast::{BroadcastKind, OngoingBroadcasts, Weight, Weights};
+use crate::dispersal;
+use crate::gossip::GossipParameters;
+use crate::byzantine;
+use crate::byzantine::Behaviour;
+use crate::crypto::{sign_transaction, SignedMessage};
+use crate::event::Event;
+use crate::messaging;
+use crate::process::{Process, ProcId};
+use crate::transport::MemoryTransport;
+use crate::utils;
+use crate::yaml::DEFAULT_BROADCAST_LIFETIME;
+
+/// Maximal number of violations of the properties printed in the report
+const MAX_PRINTED_VIOLATIONS: usize = 10;
+
+/// Money every account owns before the first round
+const INITIAL_BALANCE: Currency = 100;
+
+/// Parameters of a simulation
+#[derive(Debug)]
+pub struct SimulationConfig
+{
+    /// Number of nodes
+    pub nb_nodes: u32,
+    /// Number of accounts making transfers
+    pub nb_accounts: usize,
+    /// Number of transfers made by each account
+    pub nb_rounds: usize,
+    /// Seed of the scheduler
+    pub seed: u64,
+    /// Probability that a message is dropped instead of being delivered
+    pub drop_rate: f64,
+    /// Probability that a message is delivered but kept in flight to be delivered again
+    pub duplicate_rate: f64,
+    /// Maximal number of steps a message can be delayed
+    pub max_delay: u64,
+    /// Number of byzantine nodes
+    pub nb_byzantine: u32,
+    /// Behaviour of the byzantine nodes
+    pub byzantine_mode: Behaviour,
+    /// Voting weights of the nodes, which all weigh the same if there are none
+    pub weights: Vec<Weight>,
+    /// Protocol used to broadcast transactions
+    pub broadcast_kind: BroadcastKind,
+}
+
+/// A message sent but not yet delivered
+struct InFlight
+{
+    /// Node the message is sent to
+    to: ProcId,
+    /// First step at which the message can be delivered
+    ready_at: u64,
+    message: SignedMessage,
+}
+
+/// A node of the simulation
+struct SimNode
+{
+    process: Process,
+    ongoing_broadcasts: OngoingBroadcasts,
+    /// Messages sent to the node by the others
+    inbox: Receiver<Event>,
+}
+
+/// A transfer asked by a simulated client
+struct SubmittedTransfer
+{
+    /// Transaction signed by the client
+    transaction: Transaction,
+    /// Whether the node the transfer was asked to accepted it
+    accepted: bool,
+    /// Whether the transfer has to be validated: the node it was asked to is correct, and the client did
+    /// not sign a conflicting transaction
+    must_be_validated: bool,
+}
+
+/// Statistics about the messages handled by the scheduler
+#[derive(Default)]
+struct SchedulerStats
+{
+    delivered: usize,
+    dropped: usize,
+    duplicated: usize,
+}
+
+impl SimulationConfig
+{
+    /// Reads the parameters of a simulation from the command line arguments following `simulate`
+    pub fn from_args(args: &[String]) -> Result<SimulationConfig, String>
+    {
+        let nb_nodes = match args.first()
+        {
+            Some(nb) => { nb.parse::<u32>().map_err(|_| format!("Invalid number of nodes: {}", nb))? }
+            None => { return Err(String::from("Missing number of nodes")); }
+        };
+
+        let mut config = SimulationConfig
+        {
+            nb_nodes,
+            nb_accounts: 4,
+            nb_rounds: 3,
+            seed: 0,
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+            max_delay: 10,
+            nb_byzantine: 0,
+            byzantine_mode: Behaviour::Silent,
+            weights: vec![],
+            broadcast_kind: BroadcastKind::Reliable,
+        };
+
+        let mut index = 1;
+        while index < args.len()
+        {
+            let value = args.get(index + 1)
+                .ok_or(format!("Missing value for option {}", args[index]))?;
+            let invalid = || format!("Invalid value for option {}: {}", args[index], value);
+            match &args[index][..]
+            {
+                "--accounts" => { config.nb_accounts = value.parse().map_err(|_| invalid())?; }
+                "--rounds" => { config.nb_rounds = value.parse().map_err(|_| invalid())?; }
+                "--seed" => { config.seed = value.parse().map_err(|_| invalid())?; }
+                "--drop" => { config.drop_rate = value.parse().map_err(|_| invalid())?; }
+                "--duplicate" => { config.duplicate_rate = value.parse().map_err(|_| invalid())?; }
+                "--max-delay" => { config.max_delay = value.parse().map_err(|_| invalid())?; }
+                "--byzantine" => { config.nb_byzantine = value.parse().map_err(|_| invalid())?; }
+                "--mode" => { config.byzantine_mode = Behaviour::from_name(value)?; }
+                "--broadcast" => { config.broadcast_kind = BroadcastKind::from_name(value, nb_nodes as usize)?; }
+                "--sample" =>
+                {
+                    let sample_size = value.parse().map_err(|_| invalid())?;
+                    config.broadcast_kind = BroadcastKind::Gossip(GossipParameters::with_sample_size(sample_size));
+                }
+                "--weights" =>
+                {
+                    config.weights = value.split(',')
+                        .map(|weight| weight.parse::<Weight>().ok().filter(|weight| *weight > 0))
+                        .collect::<Option<Vec<Weight>>>()
+                        .ok_or_else(invalid)?;
+                }
+                option => { return Err(format!("Unknown option {}", option)); }
+            }
+            index += 2;
+        }
+
+        if config.nb_nodes == 0 || config.nb_accounts < 2
+        {
+            return Err(String::from("A simulation needs at least one node and two accounts"));
+        }
+        if config.nb_byzantine >= config.nb_nodes
+        {
+            return Err(String::from("A simulation needs at least one correct node"));
+        }
+        if !config.weights.is_empty() && config.weights.len() != config.nb_nodes as usize
+        {
+            return Err(String::from("A simulation needs one weight per node"));
+        }
+        let weights = config.weights()?;
+        if let BroadcastKind::Gossip(parameters) = config.broadcast_kind
+        {
+            parameters.check(config.nb_nodes as usize)?;
+        }
+        if config.broadcast_kind == BroadcastKind::Dispersal
+        {
+            dispersal::check(&weights)?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the voting weights of the nodes. Fails if their total does not fit in a [`Weight`]
+    fn weights(&self) -> Result<Weights, String>
+    {
+        match self.weights.is_empty()
+        {
+            true => { Ok(Weights::equal(self.nb_nodes as usize)) }
+            false => { Weights::from(self.weights.clone()) }
+        }
+    }
+}
+
+/// Usage of the `simulate` command
+pub const USAGE: &str = "simulate <nb-nodes> [--accounts <n>] [--rounds <n>] [--seed <n>] [--drop <p>] \
+    [--duplicate <p>] [--max-delay <steps>] [--byzantine <n>] [--mode <byzantine-mode>] [--weights <w1,w2,...>] [--broadcast <reliable|consistent|gossip|dispersal>] [--sample <size>]";
+
+/// Runs a simulation and prints its report. Returns whether every property held
+pub fn run(config: &SimulationConfig) -> bool
+{
+    // Every simulation starts from empty histories
+    let mut main_directory = env::current_exe()
+        .expect("Problem to access the current exe path");
+    main_directory.pop();
+    main_directory.push("simulation");
+    let main_directory = String::from(main_directory.to_str()
+        .expect("Failed to convert the simulation path to string"));
+    let _ = remove_dir_all(&main_directory);
+    utils::initialize(true, Some(main_directory.clone()), 0);
+    utils::set_print_logs(false);
+
+    run_in(config, &main_directory)
+}
+
+/// Runs a simulation whose nodes write their histories in the given empty directory, once the logging
+/// system is initialized, and returns whether the properties held
+fn run_in(config: &SimulationConfig, main_directory: &str) -> bool
+{
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    // Create the nodes
+    let mut senders = Vec::new();
+    let mut inboxes = Vec::new();
+    for _ in 0..config.nb_nodes
+    {
+        let (sender, receiver) = mpsc::channel();
+        senders.push(sender);
+        inboxes.push(receiver);
+    }
+    let keypairs: Vec<Keypair> = (0..config.nb_nodes).map(|_| Keypair::generate(&mut rng)).collect();
+    let public_keys: Vec<PublicKey> = keypairs.iter().map(|keypair| keypair.public).collect();
+    let weights = config.weights().expect("The weights are checked with the arguments of the simulation");
+    let mut nodes: Vec<SimNode> = MemoryTransport::network(senders).into_iter()
+        .zip(inboxes)
+        .zip(keypairs)
+        .enumerate()
+        .map(|(index, ((transport, inbox), keypair))|
+        {
+            let id = index as ProcId + 1;
+            let data_directory = format!("{}/node{}", main_directory, id);
+            utils::create_data_directories(&data_directory);
+            let behaviour = match id + config.nb_byzantine > config.nb_nodes
+            {
+                true => { config.byzantine_mode }
+                false => { Behaviour::Correct }
+            };
+            SimNode
+            {
+                process: Process::init(id, config.nb_nodes, keypair, public_keys.clone(),
+                    weights.clone(), config.broadcast_kind, Box::new(transport),
+                    data_directory, behaviour),
+                // The samples of gossip broadcasts come from the seed too
+                ongoing_broadcasts: OngoingBroadcasts::new(DEFAULT_BROADCAST_LIFETIME)
+                    .with_seed(config.seed.wrapping_add(id as u64)),
+                inbox,
+            }
+        })
+        .collect();
+
+    // Create the accounts, funded by an account which never makes transfers
+    let accounts: Vec<Keypair> = (0..config.nb_accounts).map(|_| Keypair::generate(&mut rng)).collect();
+    let genesis = user_id(&Keypair::generate(&mut rng));
+    for node in &nodes
+    {
+        for (index, account) in accounts.iter().enumerate()
+        {
+            let transaction = Transaction::from(index as SeqId + 1, genesis, user_id(account), INITIAL_BALANCE);
+            utils::write_transaction(node.process.get_data_directory(), &transaction);
+        }
+    }
+
+    let mut submitted = Vec::new();
+    let mut in_flight = Vec::new();
+    let mut stats = SchedulerStats::default();
+    let mut time = 0;
+    // Last transaction signed by each account, with its signature
+    let mut last_signed: Vec<Option<(Transaction, Vec<u8>)>> = vec![None; accounts.len()];
+    // Transactions for which a client signed a conflicting one
+    let mut equivocated: Vec<Transaction> = Vec::new();
+
+    for _ in 0..config.nb_rounds
+    {
+        for (index, account) in accounts.iter().enumerate()
+        {
+            // A client never signs two transactions with the same sequence number, so it asks for its last
+            // transaction again until it is validated
+            let seq_id = next_seq(&nodes, account);
+            let (transaction, signature) = match &last_signed[index]
+            {
+                Some((transaction, signature)) if transaction.seq_id >= seq_id => { (*transaction, signature.clone()) }
+                _ =>
+                {
+                    let recipient = &accounts[(index + rng.gen_range(1, accounts.len())) % accounts.len()];
+                    let amount = rng.gen_range(1, INITIAL_BALANCE / 2 + 1);
+                    let transaction = Transaction::from(seq_id, user_id(account), user_id(recipient), amount);
+                    (transaction, sign_transaction(&transaction, account))
+                }
+            };
+            last_signed[index] = Some((transaction, signature.clone()));
+
+            let node = rng.gen_range(0, nodes.len());
+            let correct_node = nodes[node].process.get_behaviour().is_correct();
+            let (accepted, _) = byzantine::transfer(&mut nodes[node].process, transaction, signature);
+            submitted.push(SubmittedTransfer
+            {
+                transaction,
+                accepted,
+                must_be_validated: correct_node,
+            });
+
+            // The client of an equivocating node colludes with it, and signs a conflicting transaction with
+            // the same sequence number
+            if nodes[node].process.get_behaviour() == Behaviour::Equivocate
+            {
+                let conflicting = Transaction::from(transaction.seq_id, transaction.sender_id, transaction.receiver_id,
+                    transaction.amount + 1);
+                let signature = sign_transaction(&conflicting, account);
+                let (accepted, _) = byzantine::transfer(&mut nodes[node].process, conflicting, signature);
+                submitted.push(SubmittedTransfer
+                {
+                    transaction: conflicting,
+                    accepted,
+                    must_be_validated: false,
+                });
+                equivocated.push(transaction);
+            }
+        }
+
+        collect_messages(&nodes, &mut in_flight, &mut rng, time, config.max_delay);
+        while !in_flight.is_empty()
+        {
+            // Let time pass if every message is delayed
+            let next = in_flight.iter().map(|m| m.ready_at).min().unwrap_or(time);
+            time = std::cmp::max(time, next);
+
+            let ready: Vec<usize> = (0..in_flight.len()).filter(|&i| in_flight[i].ready_at <= time).collect();
+            let index = ready[rng.gen_range(0, ready.len())];
+            let roll: f64 = rng.gen();
+
+            if roll < config.drop_rate
+            {
+                in_flight.swap_remove(index);
+                stats.dropped += 1;
+            }
+            else
+            {
+                let message = if roll < config.drop_rate + config.duplicate_rate
+                {
+                    stats.duplicated += 1;
+                    in_flight[index].ready_at = time + rng.gen_range(0, config.max_delay + 1);
+                    InFlight { to: in_flight[index].to, ready_at: time, message: in_flight[index].message.clone() }
+                }
+                else
+                {
+                    in_flight.swap_remove(index)
+                };
+
+                if let Some(node) = (message.to as usize).checked_sub(1).and_then(|index| nodes.get_mut(index))
+                {
+                    byzantine::deal_with_messages(&mut node.process, vec![message.message], &mut node.ongoing_broadcasts);
+                    node.process.valid();
+                    stats.delivered += 1;
+                }
+            }
+
+            time += 1;
+            collect_messages(&nodes, &mut in_flight, &mut rng, time, config.max_delay);
+        }
+
+        // The broadcasts which are still stuck would be given up before the clients ask again
+        let lifetime_end = Instant::now() + 2 * DEFAULT_BROADCAST_LIFETIME;
+        for node in nodes.iter_mut()
+        {
+            messaging::evict_expired_broadcasts(&mut node.process, &mut node.ongoing_broadcasts, lifetime_end);
+        }
+        collect_messages(&nodes, &mut in_flight, &mut rng, time, config.max_delay);
+    }
+
+    // A client which signed two conflicting transactions can not expect any of them to be validated
+    for transfer in submitted.iter_mut().filter(|transfer| equivocated.contains(&transfer.transaction))
+    {
+        transfer.must_be_validated = false;
+    }
+
+    report(config, &nodes, &public_keys, &accounts, &submitted, &stats)
+}
+
+/// Returns the id of the account owning the given keypair
+fn user_id(keypair: &Keypair) -> UserId
+{
+    UserId { id: keypair.public }
+}
+
+/// Returns the sequence number of the next transaction of an account, which follows the last one
+/// validated by the correct nodes, as a client asking them would
+fn next_seq(nodes: &[SimNode], account: &Keypair) -> SeqId
+{
+    nodes.iter()
+        .filter(|node| node.process.get_behaviour().is_correct())
+        .filter_map(|node| utils::load_seq(node.process.get_data_directory(), &user_id(account)).ok())
+        .max()
+        .unwrap_or(0) + 1
+}
+
+/// Moves the messages sent by the nodes to the set of messages in flight
+fn collect_messages(nodes: &[SimNode], in_flight: &mut Vec<InFlight>, rng: &mut StdRng, time: u64, max_delay: u64)
+{
+    for (index, node) in nodes.iter().enumerate()
+    {
+        while let Ok(event) = node.inbox.try_recv()
+        {
+            if let Event::Message(message) = event
+            {
+                in_flight.push(InFlight
+                {
+                    to: index as ProcId + 1,
+                    ready_at: time + rng.gen_range(0, max_delay + 1),
+                    message,
+                });
+            }
+        }
+    }
+}
+
+/// Returns the transactions issued by an account which were validated by a node
+fn sent_transactions(node: &SimNode, account: &UserId) -> Vec<Transaction>
+{
+    full_history(node, account).into_iter().filter(|t| t.sender_id == *account).collect()
+}
+
+/// Returns the transactions issued or received by an account which were validated by a node
+fn full_history(node: &SimNode, account: &UserId) -> Vec<Transaction>
+{
+    match utils::load_history(node.process.get_data_directory(), account)
+    {
+        Ok(history) => { history }
+        Err(err) =>
+        {
+            println!("Could not load the history of node {} : {}", node.process.id, err);
+            vec![]
+        }
+    }
+}
+
+/// Checks the properties of the broadcast on the histories of the nodes, prints them and returns
+/// whether they all held
+fn report(config: &SimulationConfig, nodes: &[SimNode], public_keys: &[PublicKey], accounts: &[Keypair],
+    submitted: &[SubmittedTransfer], stats: &SchedulerStats) -> bool
+{
+    let mut validity = true;
+    let mut no_duplication = true;
+    let mut integrity = true;
+    let mut consistency = true;
+    let mut totality = true;
+    let mut certified = true;
+    let mut solvent = true;
+    let mut nb_validated = 0;
+    let mut violations = Vec::new();
+
+    // Byzantine nodes can validate anything, only the correct ones are checked
+    let correct_nodes: Vec<&SimNode> = nodes.iter().filter(|node| node.process.get_behaviour().is_correct()).collect();
+
+    for keypair in accounts
+    {
+        let account = user_id(keypair);
+        let histories: Vec<Vec<Transaction>> = correct_nodes.iter()
+            .map(|node| sent_transactions(node, &account))
+            .collect();
+        let accepted: Vec<&SubmittedTransfer> = submitted.iter()
+            .filter(|s| s.accepted && s.transaction.sender_id == account)
+            .collect();
+        // Only a transfer asked to a correct node by a client which did not equivocate has to be validated
+        let broadcast_by_correct: Vec<&SubmittedTransfer> = accepted.iter()
+            .filter(|s| s.must_be_validated)
+            .copied()
+            .collect();
+
+        // Transaction validated by any node for each sequence number
+        let mut validated: HashMap<SeqId, &Transaction> = HashMap::new();
+
+        for (node, history) in correct_nodes.iter().zip(histories.iter())
+        {
+            let id = node.process.id;
+            for transaction in history
+            {
+                // No duplication: a node validates each sequence number once
+                if history.iter().filter(|t| t.seq_id == transaction.seq_id).count() > 1
+                {
+                    no_duplication = false;
+                    violations.push(format!("No duplication violated: node {} validated transfer {} of an account twice", id, transaction.seq_id));
+                }
+
+                // Integrity: only transfers asked by clients are validated
+                if !accepted.iter().any(|t| t.transaction == *transaction)
+                {
+                    integrity = false;
+                    violations.push(format!("Integrity violated: node {} validated {} which no client asked", id, transaction));
+                }
+
+                // Every validated transaction comes with a certificate proving its delivery
+                match utils::load_certificate(node.process.get_data_directory(), &account, transaction.seq_id)
+                {
+                    Ok(Some(certificate)) if certificate.transaction == *transaction
+                        && (!config.broadcast_kind.proves_delivery()
+                            || certificate.verify(public_keys, node.process.get_weights())) => {}
+                    _ =>
+                    {
+                        certified = false;
+                        violations.push(format!("Certificates violated: node {} has no valid certificate for {}", id, transaction));
+                    }
+                }
+
+                // Consistency: nodes validate the same transaction for a sequence number
+                match validated.get(&transaction.seq_id)
+                {
+                    Some(other) if *other != transaction =>
+                    {
+                        consistency = false;
+                        violations.push(format!("Consistency violated: node {} validated {} instead of {}", id, transaction, other));
+                    }
+                    Some(_) => {}
+                    None => { validated.insert(transaction.seq_id, transaction); }
+                }
+            }
+
+            // Balances: no account spends more than it received
+            let (received, spent) = full_history(node, &account).iter()
+                .fold((0u64, 0u64), |(received, spent), t| match t.receiver_id == account
+                {
+                    true => { (received + t.amount as u64, spent) }
+                    false => { (received, spent + t.amount as u64) }
+                });
+            if spent > received
+            {
+                solvent = false;
+                violations.push(format!("Balances violated: node {} validated transfers of {} encoins from an account which received {}", id, spent, received));
+            }
+
+            // Validity: every transfer accepted by a correct node is validated
+            for transfer in &broadcast_by_correct
+            {
+                if !history.contains(&transfer.transaction)
+                {
+                    validity = false;
+                    violations.push(format!("Validity violated: node {} did not validate {}", id, transfer.transaction));
+                }
+            }
+        }
+
+        // Totality: a transaction validated by a node is validated by all of them
+        for (seq_id, transaction) in &validated
+        {
+            for (node, history) in correct_nodes.iter().zip(histories.iter())
+            {
+                if !history.iter().any(|t| t.seq_id == *seq_id)
+                {
+                    totality = false;
+                    violations.push(format!("Totality violated: node {} did not validate {}", node.process.id, transaction));
+                }
+            }
+        }
+        nb_validated += validated.len();
+    }
+
+    for violation in violations.iter().take(MAX_PRINTED_VIOLATIONS)
+    {
+        println!("{}", violation);
+    }
+    if violations.len() > MAX_PRINTED_VIOLATIONS
+    {
+        println!("... and {} other violations", violations.len() - MAX_PRINTED_VIOLATIONS);
+    }
+
+    println!("Simulation of {} nodes with seed {}", config.nb_nodes, config.seed);
+    if config.nb_byzantine > 0
+    {
+        println!("  byzantine nodes     : {} ({})", config.nb_byzantine, config.byzantine_mode);
+    }
+    println!("  broadcast           : {}", config.broadcast_kind);
+    println!("  transfers asked     : {}", submitted.len());
+    println!("  transfers accepted  : {}", submitted.iter().filter(|s| s.accepted).count());
+    println!("  transfers validated : {}", nb_validated);
+    println!("  messages delivered  : {} ({} duplicated, {} dropped)", stats.delivered, stats.duplicated, stats.dropped);
+    let properties = [("validity", validity), ("no duplication", no_duplication), ("integrity", integrity),
+        ("consistency", consistency), ("totality", totality), ("certificates", certified), ("balances", solvent)];
+    for (name, held) in properties.iter()
+    {
+        println!("  {:<20}: {}", name, if *held { "held" } else { "VIOLATED" });
+    }
+
+    properties.iter().all(|(_, held)| *held)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_utils;
+
+    /// Byzantine modes the simulations are run with
+    const BYZANTINE_MODES: [&str; 5] = ["silent", "crash-after:3", "equivocate", "forge-echo", "eager-ready"];
+
+    /// Runs a seeded simulation of 7 nodes with the given options, in an empty directory for the test of
+    /// the given name, and returns whether the properties held
+    fn simulate(name: &str, options: &str) -> bool
+    {
+        test_utils::initialize_logs();
+        let args: Vec<String> = format!("7 --seed 1 --duplicate 0.05 {}", options).split_whitespace().map(String::from).collect();
+        let config = SimulationConfig::from_args(&args).expect("The options of the simulation are valid");
+        run_in(&config, &test_utils::directory(name))
+    }
+
+    /// Checks that the properties of a broadcast hold when every node is correct, and when two nodes are
+    /// byzantine, whatever their mode
+    fn check_properties(broadcast: &str)
+    {
+        assert!(simulate(&format!("simulation-{}", broadcast), &format!("--broadcast {}", broadcast)),
+            "The properties of the {} broadcast do not hold", broadcast);
+        for mode in BYZANTINE_MODES.iter()
+        {
+            let name = format!("simulation-{}-{}", broadcast, mode.replace(':', "-"));
+            assert!(simulate(&name, &format!("--broadcast {} --byzantine 2 --mode {}", broadcast, mode)),
+                "The properties of the {} broadcast do not hold with {} nodes", broadcast, mode);
+        }
+    }
+
+    #[test]
+    fn reliable_broadcasts_keep_their_properties()
+    {
+        check_properties("reliable");
+    }
+
+    #[test]
+    fn consistent_broadcasts_keep_their_properties()
+    {
+        check_properties("consistent");
+    }
+
+    #[test]
+    fn gossip_broadcasts_keep_their_properties()
+    {
+        check_properties("gossip");
+    }
+
+    #[test]
+    fn dispersal_broadcasts_keep_their_properties()
+    {
+        check_properties("dispersal");
+    }
+}
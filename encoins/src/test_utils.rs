@@ -0,0 +1,96 @@
+//! Fixtures shared by the tests of the modules
+
+use std::env;
+use std::fs;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::Once;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use encoins_api::base_types::{Currency, SeqId, Transaction, UserId};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use crate::broadcast::{BroadcastKind, Weights};
+use crate::byzantine::Behaviour;
+use crate::event::Event;
+use crate::message::{Message, MessageType};
+use crate::process::{Process, ProcId};
+use crate::transport::MemoryTransport;
+use crate::utils;
+
+/// Returns an account whose key is the same in every test
+pub fn account() -> UserId
+{
+    UserId { id : PublicKey::from(&SecretKey::from_bytes(&[7; 32]).expect("32 bytes make a secret key")) }
+}
+
+/// Returns an account drawn from the given seed, with the keypair of its owner
+pub fn owner(seed : u64) -> (UserId, Keypair)
+{
+    let keypair = Keypair::generate(&mut StdRng::seed_from_u64(seed));
+    (UserId { id : keypair.public }, keypair)
+}
+
+/// Returns the message of type `message_type` server `sender_id` sends for transaction `seq_id` of [`account`],
+/// which sends `amount` to itself, in a broadcast started by server 1. The message carries no dependencies
+/// and a transfer signature which is not valid
+pub fn message(message_type : MessageType, sender_id : ProcId, seq_id : SeqId, amount : Currency) -> Message
+{
+    Message::new(Transaction::from(seq_id, account(), account(), amount), vec![], message_type, sender_id, 1, vec![0; 64])
+}
+
+/// Initializes the logging system for the tests, which print no logs
+pub fn initialize_logs()
+{
+    static INITIALIZE : Once = Once::new();
+    INITIALIZE.call_once(||
+    {
+        utils::set_print_logs(false);
+        utils::initialize(false, Some(directory("logs")), 0);
+    });
+}
+
+/// Returns an empty directory for the test of the given name
+pub fn directory(name : &str) -> String
+{
+    let path = env::temp_dir().join("encoins-tests").join(name);
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).expect("Impossible to create a directory for a test");
+    String::from(path.to_str().expect("Failed to convert the path of a test directory to string"))
+}
+
+/// Returns `nb_processes` correct processes using the given broadcast and wired together by a [`MemoryTransport`],
+/// with the channels on which they receive their messages. Their histories are written in an empty directory for
+/// the test of the given name
+pub fn network(name : &str, nb_processes : u32, broadcast_kind : BroadcastKind) -> Vec<(Process, Receiver<Event>)>
+{
+    initialize_logs();
+    let directory = directory(name);
+    let mut rng = StdRng::seed_from_u64(0);
+    let keypairs : Vec<Keypair> = (0..nb_processes).map(|_| Keypair::generate(&mut rng)).collect();
+    let public_keys : Vec<PublicKey> = keypairs.iter().map(|keypair| keypair.public).collect();
+    let (senders, inboxes) : (Vec<_>, Vec<_>) = (0..nb_processes).map(|_| mpsc::channel()).unzip();
+
+    MemoryTransport::network(senders).into_iter()
+        .zip(keypairs)
+        .zip(inboxes)
+        .enumerate()
+        .map(|(index, ((transport, keypair), inbox))|
+        {
+            let id = index as ProcId + 1;
+            let data_directory = format!("{}/node{}", directory, id);
+            utils::create_data_directories(&data_directory);
+            let process = Process::init(id, nb_processes, keypair, public_keys.clone(), Weights::equal(nb_processes as usize),
+                broadcast_kind, Box::new(transport), data_directory, Behaviour::Correct);
+            (process, inbox)
+        })
+        .collect()
+}
+
+/// Gives `amount` encoins to an account in the histories of the processes, from [`account`]
+pub fn fund(network : &[(Process, Receiver<Event>)], receiver : UserId, amount : Currency)
+{
+    for (process, _) in network
+    {
+        utils::write_transaction(process.get_data_directory(), &Transaction::from(1, account(), receiver, amount));
+    }
+}
@@ -0,0 +1,175 @@
+//! Abstraction of the network used by the servers to exchange messages
+//!
+//! A [`Transport`] sends [`SignedMessage`]s to the other servers, and delivers the messages it
+//! receives as [`Event::Message`]s on the channel given when it was created.
+//! Two implementations are available:
+//! - [`TcpTransport`], used by real nodes, which relies on TCP and secure channels ;
+//! - [`MemoryTransport`], which wires together nodes running in the same program through channels.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use crate::broadcast::BroadcastId;
+use crate::connection_pool::ConnectionPool;
+use crate::crypto::SignedMessage;
+use crate::event::Event;
+use crate::process::ProcId;
+use crate::secure_channel::ChannelKeys;
+use crate::serv_network::server_listener;
+
+/// A way to send messages to the servers of the network
+pub trait Transport: Debug + Send
+{
+    /// Sends a message to server `to`
+    fn send(&self, to: ProcId, message: SignedMessage);
+
+    /// Returns the ids of every server reachable through the transport, including the local one
+    fn peers(&self) -> Vec<ProcId>;
+
+    /// Sends a message to every server of the network
+    fn broadcast(&self, message: SignedMessage)
+    {
+        for peer in self.peers()
+        {
+            self.send(peer, message.clone());
+        }
+    }
+
+    /// Gives up sending the messages of a broadcast the process forgot. Transports which deliver
+    /// messages right away have nothing to do
+    fn forget(&self, _broadcast_id: BroadcastId) {}
+}
+
+/// Transport over TCP, using one long-lived secure connection per server
+#[derive(Debug)]
+pub struct TcpTransport
+{
+    pool: ConnectionPool,
+    nb_process: u32,
+}
+
+impl TcpTransport
+{
+    /// Starts listening on `server_socket` and connecting to the servers at `serv_addr`,
+    /// the i-th address being the one of server i+1
+    pub fn start(server_socket: (String, u16), serv_addr: &[(String, u16)], keys: Arc<ChannelKeys>,
+        events: Sender<Event>) -> TcpTransport
+    {
+        let listener_keys = keys.clone();
+        thread::spawn( move ||{server_listener(server_socket, events, listener_keys);});
+
+        TcpTransport
+        {
+            pool: ConnectionPool::new(serv_addr, keys),
+            nb_process: serv_addr.len() as u32,
+        }
+    }
+}
+
+impl Transport for TcpTransport
+{
+    fn send(&self, to: ProcId, message: SignedMessage)
+    {
+        self.pool.send(to, message);
+    }
+
+    fn peers(&self) -> Vec<ProcId>
+    {
+        (1..self.nb_process + 1).collect()
+    }
+
+    fn broadcast(&self, message: SignedMessage)
+    {
+        self.pool.broadcast(message);
+    }
+
+    fn forget(&self, broadcast_id: BroadcastId)
+    {
+        self.pool.forget(broadcast_id);
+    }
+}
+
+/// Transport between nodes of the same program. Messages are delivered immediately on the event
+/// channel of the receiving node
+#[derive(Debug)]
+pub struct MemoryTransport
+{
+    /// Event channels of every node, the i-th one being the one of node i+1
+    nodes: Vec<Sender<Event>>,
+}
+
+impl MemoryTransport
+{
+    /// Wires together nodes given their event channels, the i-th channel being the one of node i+1.
+    /// Returns the transport of each node, in the same order
+    pub fn network(nodes: Vec<Sender<Event>>) -> Vec<MemoryTransport>
+    {
+        nodes.iter()
+            .map(|_| MemoryTransport { nodes: nodes.clone() })
+            .collect()
+    }
+}
+
+impl Transport for MemoryTransport
+{
+    fn send(&self, to: ProcId, message: SignedMessage)
+    {
+        if let Some(node) = (to as usize).checked_sub(1).and_then(|index| self.nodes.get(index))
+        {
+            // A node which stopped simply does not receive the message
+            let _ = node.send(Event::Message(message));
+        }
+    }
+
+    fn peers(&self) -> Vec<ProcId>
+    {
+        (1..self.nodes.len() as ProcId + 1).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::mpsc::Receiver;
+    use crate::message::MessageType;
+    use crate::test_utils;
+
+    /// Returns the ids of the senders of the messages received on an event channel
+    fn received(inbox : &Receiver<Event>) -> Vec<ProcId>
+    {
+        inbox.try_iter()
+            .map(|event| match event
+            {
+                Event::Message(signed_message) => { signed_message.message.sender_id }
+                Event::Instruction(_) => { panic!("A transport only delivers messages") }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn memory_transports_deliver_messages_between_nodes()
+    {
+        let (senders, inboxes) : (Vec<_>, Vec<_>) = (0..2).map(|_| mpsc::channel()).unzip();
+        let transports = MemoryTransport::network(senders);
+        let message = |sender_id| SignedMessage { message : test_utils::message(MessageType::Echo, sender_id, 1, 10), signature : vec![] };
+        assert_eq!(transports[0].peers(), vec![1, 2]);
+
+        transports[0].send(2, message(1));
+        transports[1].send(1, message(2));
+        transports[1].send(2, message(2));
+        assert_eq!(received(&inboxes[0]), vec![2]);
+        assert_eq!(received(&inboxes[1]), vec![1, 2]);
+
+        transports[1].broadcast(message(2));
+        assert_eq!(received(&inboxes[0]), vec![2]);
+        assert_eq!(received(&inboxes[1]), vec![2]);
+
+        // Messages to servers which do not exist are dropped
+        transports[0].send(0, message(1));
+        transports[0].send(3, message(1));
+        assert!(received(&inboxes[0]).is_empty() && received(&inboxes[1]).is_empty());
+    }
+}
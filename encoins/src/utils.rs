@@ -1,35 +1,46 @@
 //! A simple logging system to log infos about processes
 
 use std::env;
+use std::fs;
 use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use std::io::{Read, Write};
 use std::path::Path;
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
 use chrono::prelude::*;
 use encoins_api::base_types::*;
+use crate::certificate::DeliveryCertificate;
+use crate::errors::{decode, DecodeError};
 use crate::process::TransferSet;
 
-/// States if the logging system has been initialized
-static mut INITIALIZED: bool = false;
+/// Initializes the logging system once
+static INITIALIZED: Once = Once::new();
 /// States whether logs should be written
-static mut WRITE_LOGS: bool = true;
+static WRITE_LOGS: AtomicBool = AtomicBool::new(true);
 /// Path to the main directory
-pub static mut MAIN_DIRECTORY_PATH : String = String::new();
+pub static MAIN_DIRECTORY_PATH : Mutex<String> = Mutex::new(String::new());
 /// Path to the logging directory
-pub static mut LOGS_DIRECTORY_PATH : String = String::new();
-/// Path to the HISTS directory
-pub static mut HISTS_DIRECTORY_PATH : String = String::new();
-///Path to the SEQS directory
-pub static mut SEQS_DIRECTORY_PATH : String = String::new();
+pub static LOGS_DIRECTORY_PATH : Mutex<String> = Mutex::new(String::new());
 /// Path to the file where logs are written
-pub static mut LOGGING_FILE_PATH : String = String::new();
+pub static LOGGING_FILE_PATH : Mutex<String> = Mutex::new(String::new());
+/// States whether logs should be printed on the standard output
+static PRINT_LOGS: AtomicBool = AtomicBool::new(true);
+/// Name of the HISTS directory in a data directory
+const HISTS_DIRECTORY: &str = "hists";
+/// Name of the SEQS directory in a data directory
+const SEQS_DIRECTORY: &str = "seqs";
+/// Name of the directory of the delivery certificates in a data directory
+const CERTS_DIRECTORY: &str = "certs";
 
 /// Formats the given message with its parameters into a log message
 /// # Examples
 ///
-/// ```
+/// ```no_run
+/// use encoins::log;
 /// log!("hello there!"); // Logs the message "hello there"
 ///```
-/// ```
+/// ```no_run
+/// use encoins::log;
 /// log!("format {} arguments", "some"); // Logs the message "format some arguments"
 /// ```
 #[macro_export]
@@ -50,11 +61,13 @@ macro_rules! log {
 /// Formats the given message with its parameters into an uppercase crash message
 /// # Examples
 ///
-/// ```
-/// warn!("hello there!"); // Logs the message "/!\ HELLO THERE /!\"
+/// ```no_run
+/// use encoins::crash_with;
+/// crash_with!("hello there!"); // Logs the message "/!\ HELLO THERE /!\"
 ///```
-/// ```
-/// warn!("format {} arguments", "some"); // Logs the message "/!\ FORMAT SOME ARGUMENTS /!\"
+/// ```no_run
+/// use encoins::crash_with;
+/// crash_with!("format {} arguments", "some"); // Logs the message "/!\ FORMAT SOME ARGUMENTS /!\"
 /// ```
 #[macro_export]
 macro_rules! crash_with
@@ -84,75 +97,82 @@ macro_rules! crash_with
 /// Otherwise, the main directory will be created at the given path
 pub fn initialize(write_logs : bool, main_file_path : Option<String>, proc_nb : u32)
 {
-    unsafe
+    WRITE_LOGS.store(write_logs, Ordering::SeqCst);
+
+    INITIALIZED.call_once(||
+    {
+        // Start by defining the main path
+        let main_directory_path = match main_file_path
+        {
+            None =>
+                {
+                    let mut exec_file_path = env::current_exe()
+                        .expect("Problem to access the current exe path");
+                    exec_file_path.pop();
+                    format!("{}/files{}", exec_file_path.to_str()
+                        .expect("Failed to convert current exe path to string"), proc_nb)
+                }
+            Some(path) =>
+                {
+                    path
+                }
+        };
+
+        // Logs file will be written in main_path/logs
+        let logs_directory_path = format!("{}/logs", main_directory_path);
+
+        // Create paths
+        create_dir_all(&logs_directory_path)
+            .expect("Impossible to create a directory for logs");
+        create_data_directories(&main_directory_path);
+
+        // Create log file path for this execution
+        let date = Local::now().format("%Y_%m_%d");
+        let logging_file_prefix = format!("{}/{}_", logs_directory_path, date);
+        let mut iteration = 1;
+        let mut logging_file_path = format!("{}{}.txt", logging_file_prefix, iteration);
+        while Path::new(&logging_file_path).exists()
         {
-            WRITE_LOGS = write_logs;
+            iteration += 1;
+            logging_file_path = format!("{}{}.txt", logging_file_prefix, iteration);
         }
+        File::create(&logging_file_path)
+            .expect("Impossible to create a file for logging");
 
+        *lock(&MAIN_DIRECTORY_PATH) = main_directory_path;
+        *lock(&LOGS_DIRECTORY_PATH) = logs_directory_path;
+        *lock(&LOGGING_FILE_PATH) = logging_file_path;
+    });
+}
 
-    unsafe {
-
-        if !INITIALIZED
-        {
-            INITIALIZED = true;
-
-            // Start by defining the main path
-            match main_file_path
-            {
-                None =>
-                    {
-                        let mut exec_file_path = env::current_exe()
-                            .expect("Problem to access the current exe path");
-                        exec_file_path.pop();
-                        MAIN_DIRECTORY_PATH = String::from(exec_file_path.to_str()
-                            .expect("Failed to convert current exe path to string"));
-                        MAIN_DIRECTORY_PATH.push_str("/files");
-                        MAIN_DIRECTORY_PATH = String::from(format!("{}{}", MAIN_DIRECTORY_PATH, proc_nb));
-                    }
-                Some(path) =>
-                    {
-                        MAIN_DIRECTORY_PATH = String::from(path);
-                    }
-            }
-
-            // History files will be written in main_path/hists
-            HISTS_DIRECTORY_PATH = MAIN_DIRECTORY_PATH.clone();
-            HISTS_DIRECTORY_PATH.push_str("/hists");
-
-            // Logs file will be written in main_path/logs
-            LOGS_DIRECTORY_PATH = MAIN_DIRECTORY_PATH.clone();
-            LOGS_DIRECTORY_PATH.push_str("/logs");
-
-            // Seq files will be writtent in main_path/seqs
-            SEQS_DIRECTORY_PATH = MAIN_DIRECTORY_PATH.clone();
-            SEQS_DIRECTORY_PATH.push_str("/seqs");
-
-            // Create paths
-            create_dir_all(LOGS_DIRECTORY_PATH.clone())
-                .expect("Impossible to create a directory for logs");
-            create_dir_all(HISTS_DIRECTORY_PATH.clone())
-                .expect("Impossible to create a directory for hists");
-            create_dir_all(SEQS_DIRECTORY_PATH.clone())
-                .expect("Impossible to create a directory for seqs");
-
-            // Create log file path for this execution
-            let date = Local::now().format("%Y_%m_%d");
-            LOGGING_FILE_PATH = format!("{}/{}_", LOGS_DIRECTORY_PATH, date);
-            let mut iteration = 1;
-            let mut temp_logging_path = format!("{}{}.txt", LOGGING_FILE_PATH, iteration);
-            while Path::new(&temp_logging_path.to_string()).exists()
-            {
-                iteration += 1;
-                temp_logging_path = format!("{}{}.txt", LOGGING_FILE_PATH, iteration);
-            }
-            LOGGING_FILE_PATH = format!("{}", temp_logging_path);
-            File::create(LOGGING_FILE_PATH.clone())
-                .expect("Impossible to create a file for logging");
+/// Locks one of the paths of the logging system, which stays usable if a thread panicked while holding it
+fn lock(path : &Mutex<String>) -> MutexGuard<'_, String>
+{
+    path.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
-        }
+/// Creates the directories where a node writes its HISTS, SEQS and certificate files.
+/// History files are written in `directory/hists`, seq files in `directory/seqs` and certificates in `directory/certs`
+pub fn create_data_directories(directory : &str)
+{
+    create_dir_all(format!("{}/{}", directory, HISTS_DIRECTORY))
+        .expect("Impossible to create a directory for hists");
+    create_dir_all(format!("{}/{}", directory, SEQS_DIRECTORY))
+        .expect("Impossible to create a directory for seqs");
+    create_dir_all(format!("{}/{}", directory, CERTS_DIRECTORY))
+        .expect("Impossible to create a directory for certs");
+}
 
-    }
+/// Returns the path to the main directory
+pub fn main_directory_path() -> String
+{
+    lock(&MAIN_DIRECTORY_PATH).clone()
+}
 
+/// Sets whether logs should be printed on the standard output. They are still written to the log file
+pub fn set_print_logs(print_logs : bool)
+{
+    PRINT_LOGS.store(print_logs, Ordering::SeqCst);
 }
 
 /// Writes the given string to the right log file
@@ -160,198 +180,200 @@ pub fn initialize(write_logs : bool, main_file_path : Option<String>, proc_nb :
 /// `write_log` should only be used by [`log!`]. To write logs use the latter.
 pub fn write_log(to_write : String, crash_msg : bool)
 {
-    unsafe
+    // Adding local time to the logs
+    let now = Local::now();
+    let final_string = format!("[{}] : {}", now.format("%H:%M"), to_write);
+    if !crash_msg && PRINT_LOGS.load(Ordering::SeqCst)
+    {
+        println!("{}", final_string);
+    }
+    if WRITE_LOGS.load(Ordering::SeqCst)
+    {
+        if !INITIALIZED.is_completed()
         {
-            // Adding local time to the logs
-            let now = Local::now();
-            let final_string = format!("[{}] : {}", now.format("%H:%M"), to_write);
-            if !crash_msg
-            {
-                println!("{}", final_string);
-            }
-            if WRITE_LOGS
-            {
-                if !INITIALIZED
-                {
-                    // If it was not initialized we crash_with!() because we can't let threads try creating files simultaneously
-                    crash_with!("The logging system has not been initialized!");
-                }
-
-                let mut file = match OpenOptions::new().write(true).append(true).open(LOGGING_FILE_PATH.clone())
-                {
-                    Ok( f) => {f}
-                    Err(_) => { crash_with!("Could not access path {}", LOGGING_FILE_PATH); }
-                };
-                let log_final_string = format!("{}\n", final_string);
-                file.write_all(log_final_string.as_bytes())
-                    .expect("Difficulties to write in a log file");
-            }
+            // If it was not initialized we crash_with!() because we can't let threads try creating files simultaneously
+            crash_with!("The logging system has not been initialized!");
         }
+
+        let logging_file_path = lock(&LOGGING_FILE_PATH).clone();
+        let mut file = match OpenOptions::new().append(true).open(&logging_file_path)
+        {
+            Ok( f) => {f}
+            Err(_) => { crash_with!("Could not access path {}", logging_file_path); }
+        };
+        let log_final_string = format!("{}\n", final_string);
+        file.write_all(log_final_string.as_bytes())
+            .expect("Difficulties to write in a log file");
+    }
 }
 
 
-pub fn load_history(user : &UserId) -> Result<TransferSet, String>
+/// Loads the history of an account from the data directory of a node
+pub fn load_history(directory : &str, user : &UserId) -> Result<TransferSet, DecodeError>
+{
+    let path = format!( "{}/{}/{}.csv",directory, HISTS_DIRECTORY, user);
+    log!("Trying to read file {}", path);
+    match File::open(&path)
+    {
+        Ok(file) => { parse_history(file) }
+        //If nos such file exist, return an empty history
+        Err(_) => { Ok(vec![]) }
+    }
+}
+
+/// Parses the content of a history file, each line being a transaction written as
+/// `seq_id,sender_id,receiver_id,amount`
+pub fn parse_history<R: Read>(content : R) -> Result<TransferSet, DecodeError>
 {
     let mut hist : TransferSet = vec![];
-    unsafe
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(content);
+    for (index, result) in reader.records().enumerate()
+    {
+        let line = index + 1;
+        let record = match result
         {
-            let path = format!( "{}/{}.csv",HISTS_DIRECTORY_PATH, user.to_string());
-            log!("Trying to read file {}", path);
-            //match csv::Reader::from_path(path)
-            match csv::ReaderBuilder::new().has_headers(false).from_path(path)
-            {
-                Ok(mut reader) =>
-                    {
-                        for result in reader.records()
-                        {
-
-                            match result
-                            {
-                                Ok(res) =>
-                                    {
-
-                                        let seq_id = match &res[0].parse::<SeqId>()
-                                        {
-                                            Ok(seqid) => { *seqid }
-                                            Err(err) => { return Err(err.to_string()) }
-                                        };
-
-                                        let sender_id = match UserId::from_string( &String::from(&res[1]))
-                                        {
-                                            Ok(pk) => { pk }
-                                            Err(err) => { return Err(err) }
-                                        };
-
-                                        let receiver_id = match UserId::from_string(&String::from(&res[2]))
-                                        {
-                                            Ok(pk) => { pk }
-                                            Err(err) => { return  Err(err) }
-                                        };
-
-                                        let amount = match &res[3].parse::<Currency>()
-                                        {
-                                            Ok(currency) => { *currency }
-                                            Err(err) => { return Err(err.to_string()) }
-                                        };
-
-                                        let transaction = Transaction::from(seq_id,
-                                                                            sender_id,
-                                                                            receiver_id,
-                                                                            amount);
-                                        hist.push(transaction);
-                                    }
-                                Err(err) =>
-                                    {
-                                       return Err(err.to_string())
-                                    }
-                            }
-
-                        }
-                    }
-                Err(_) =>
-                    {
-                        //If nos such file exist, return an empty history
-                    }
-            }
-        }
+            Ok(record) => { record }
+            Err(err) => { return Err(DecodeError::Io(err.to_string())) }
+        };
+        let field = |position : usize, name : &'static str|
+        {
+            record.get(position).ok_or(DecodeError::MissingField { line, field: name })
+        };
+        let invalid = |name : &'static str, value : &str|
+        {
+            DecodeError::InvalidField { line, field: name, value: String::from(value) }
+        };
+
+        let seq_id = field(0, "seq_id")?;
+        let seq_id = seq_id.parse::<SeqId>().map_err(|_| invalid("seq_id", seq_id))?;
+
+        let sender_id = field(1, "sender_id")?;
+        let sender_id = parse_user_id(sender_id).ok_or_else(|| invalid("sender_id", sender_id))?;
+
+        let receiver_id = field(2, "receiver_id")?;
+        let receiver_id = parse_user_id(receiver_id).ok_or_else(|| invalid("receiver_id", receiver_id))?;
+
+        let amount = field(3, "amount")?;
+        let amount = amount.parse::<Currency>().map_err(|_| invalid("amount", amount))?;
+
+        hist.push(Transaction::from(seq_id, sender_id, receiver_id, amount));
+    }
+
+    Ok(hist)
+}
 
-        return Ok(hist)
+/// Parses a user id written as the hexadecimal encoding of its public key
+fn parse_user_id(value : &str) -> Option<UserId>
+{
+    // Only well-formed values are given to the API
+    if value.len() != 2 * ed25519_dalek::PUBLIC_KEY_LENGTH || !value.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    UserId::from_string(&String::from(value)).ok()
 }
 
-pub fn load_seq(user : &UserId) -> Result<SeqId, String>
+/// Loads the sequence number of the last validated transfer of an account from the data directory of a node
+pub fn load_seq(directory : &str, user : &UserId) -> Result<SeqId, DecodeError>
 {
-    unsafe
+    let path = format!( "{}/{}/{}.seq",directory, SEQS_DIRECTORY, user);
+    log!("Trying to read file {}", path);
+    match fs::read_to_string(&path)
+    {
+        Ok(content) => { parse_seq(&content) }
+        Err(_) => { Ok(0 as SeqId) }
+    }
+}
+
+/// Parses the content of a seq file, whose first line is a sequence number. An empty file stands for 0
+pub fn parse_seq(content : &str) -> Result<SeqId, DecodeError>
+{
+    match content.lines().next()
+    {
+        None => { Ok(0 as SeqId) }
+        Some(value) =>
         {
-            let path = format!( "{}/{}.seq",SEQS_DIRECTORY_PATH, user.to_string());
-            log!("Trying to read file {}", path);
-            let file = match File::open(&path)
-            {
-                Ok(f) => {f}
-                Err(_) => { return Ok(0 as SeqId) }
-            };
-
-            let file = BufReader::new(file);
-            match file.lines().next()
-            {
-                None =>
-                    {
-                        return Ok(0 as SeqId)
-                    }
-                Some(value) =>
-                    {
-                        match value
-                        {
-                            Ok(num) =>
-                                {
-                                    match num.parse::<SeqId>()
-                                    {
-                                        Ok(seq_id) =>
-                                            {
-                                                return Ok(seq_id)
-                                            }
-                                        Err(_) =>
-                                            {
-                                                crash_with!("File {} is corrupted! Program cannot continue correctly...", path);
-                                            }
-                                    }
-                                }
-                            Err(err) =>
-                                {
-                                    return Err(err.to_string());
-                                }
-                        }
-                    }
-            };
+            value.trim().parse::<SeqId>()
+                .map_err(|_| DecodeError::InvalidField { line: 1, field: "seq_id", value: String::from(value) })
         }
+    }
 }
 
-pub fn write_transaction(transaction : &Transaction)
+/// Writes a validated transaction in the data directory of a node
+pub fn write_transaction(directory : &str, transaction : &Transaction)
 {
-    unsafe
+    let path_receiver = format!( "{}/{}/{}.csv",directory, HISTS_DIRECTORY, transaction.receiver_id);
+    let path_sender = format!( "{}/{}/{}.csv",directory, HISTS_DIRECTORY, transaction.sender_id);
+    let path_seq_sender = format!("{}/{}/{}.seq", directory, SEQS_DIRECTORY, transaction.sender_id);
+
+    let file_receiver = match OpenOptions::new().create(true).append(true).open(path_receiver)
+    {
+        Ok(f) => { f }
+        Err(error) => { crash_with!("Error : {}", error); }
+    };
+
+    let file_sender = match OpenOptions::new().create(true).append(true).open(path_sender)
+    {
+        Ok(f) => { f }
+        Err(error) => { crash_with!("Error : {}", error); }
+    };
+
+    let mut writer =  csv::Writer::from_writer(file_receiver);
+    writer.write_record(&[transaction.seq_id.to_string(), transaction.sender_id.to_string(),
+        transaction.receiver_id.to_string(), transaction.amount.to_string()])
+        .expect("Difficulty to write record on csv file");
+    writer.flush()
+        .expect("Difficulty to flush the csv writer");
+
+    writer = csv::Writer::from_writer(file_sender);
+    writer.write_record(&[transaction.seq_id.to_string(), transaction.sender_id.to_string(),
+        transaction.receiver_id.to_string(), transaction.amount.to_string()])
+        .expect("Difficulty to write record on csv file");
+    writer.flush()
+        .expect("Difficulty to flush the csv writer");
+
+    let mut file = match OpenOptions::new().create(true).write(true).truncate(true).open(path_seq_sender)
+    {
+        Ok(f) => { f }
+        Err(err) => { crash_with!("Error : {}", err); }
+    };
+    match file.write_all(transaction.seq_id.to_string().as_bytes())
+    {
+        Ok(_) => {}
+        Err(_) =>
         {
-            let path_receiver = format!( "{}/{}.csv",HISTS_DIRECTORY_PATH, &transaction.receiver_id.to_string());
-            let path_sender = format!( "{}/{}.csv",HISTS_DIRECTORY_PATH, &transaction.sender_id.to_string());
-            let path_seq_sender = format!("{}/{}.seq", SEQS_DIRECTORY_PATH, &transaction.sender_id.to_string());
-
-            let file_receiver = match OpenOptions::new().write(true).create(true).append(true).open(path_receiver)
-            {
-                Ok(f) => { f }
-                Err(error) => { crash_with!("Error : {}", error); }
-            };
-
-            let file_sender = match OpenOptions::new().write(true).create(true).append(true).open(path_sender)
-            {
-                Ok(f) => { f }
-                Err(error) => { crash_with!("Error : {}", error); }
-            };
-
-            let mut writer =  csv::Writer::from_writer(file_receiver);
-            writer.write_record(&[transaction.seq_id.to_string(), transaction.sender_id.to_string(),
-                transaction.receiver_id.to_string(), transaction.amount.to_string()])
-                .expect("Difficulty to write record on csv file");
-            writer.flush()
-                .expect("Difficulty to flush the csv writer");
-
-            writer = csv::Writer::from_writer(file_sender);
-            writer.write_record(&[transaction.seq_id.to_string(), transaction.sender_id.to_string(),
-                transaction.receiver_id.to_string(), transaction.amount.to_string()])
-                .expect("Difficulty to write record on csv file");
-            writer.flush()
-                .expect("Difficulty to flush the csv writer");
-
-            let mut file = match OpenOptions::new().create(true).write(true).truncate(true).open(path_seq_sender)
-            {
-                Ok(f) => { f }
-                Err(err) => { crash_with!("Error : {}", err); }
-            };
-            match file.write_all(transaction.seq_id.to_string().as_bytes())
-            {
-                Ok(_) => {}
-                Err(_) =>
-                {
-                    log!("Problem when writing transctions");
-                }
-            }
-            file.flush().expect("Difficulty to flush the csv writer");
+            log!("Problem when writing transctions");
         }
-}
\ No newline at end of file
+    }
+    file.flush().expect("Difficulty to flush the csv writer");
+}
+
+/// Writes the certificate of a validated transaction in the data directory of a node
+pub fn write_certificate(directory : &str, certificate : &DeliveryCertificate)
+{
+    let path = certificate_path(directory, &certificate.transaction.sender_id, certificate.transaction.seq_id);
+    let serialized = bincode::serialize(certificate)
+        .expect("Problem with the serialization of a certificate");
+    if let Err(err) = fs::write(&path, serialized)
+    {
+        log!("Could not write certificate file {} : {}", path, err);
+    }
+}
+
+/// Loads the certificate of the transaction `seq_id` of an account from the data directory of a node.
+/// Returns `None` if the transaction was not validated
+pub fn load_certificate(directory : &str, user : &UserId, seq_id : SeqId) -> Result<Option<DeliveryCertificate>, DecodeError>
+{
+    let path = certificate_path(directory, user, seq_id);
+    match fs::read(&path)
+    {
+        Ok(bytes) => { decode(&bytes).map(Some) }
+        Err(_) => { Ok(None) }
+    }
+}
+
+/// Returns the path of the certificate of the transaction `seq_id` of an account
+fn certificate_path(directory : &str, user : &UserId, seq_id : SeqId) -> String
+{
+    format!("{}/{}/{}_{}.cert", directory, CERTS_DIRECTORY, user, seq_id)
+}
@@ -1,6 +1,14 @@
 extern crate yaml_rust;
 use yaml_rust::yaml::{Hash, Yaml, YamlLoader};
 use std::fs;
+use std::time::Duration;
+use ed25519_dalek::PublicKey;
+use crate::broadcast::{BroadcastKind, Weight};
+
+/// Lifetime of a broadcast when the configuration does not give one
+pub const DEFAULT_BROADCAST_LIFETIME: Duration = Duration::from_secs(30);
+/// Public key of a server whose identity key was not written in the configuration yet
+pub const UNSET_PUBLIC_KEY: &str = "unset";
 
 /// Transform the yaml file into a Hash table
 pub fn yaml_to_hash(file: &str) -> Hash 
@@ -32,6 +40,16 @@ fn read_yaml(hash: &Hash, key1: &str, key2: &str) -> Yaml
         .clone()
 }
 
+/// Access hash[key1][key2], if key2 is present
+fn read_optional_yaml(hash: &Hash, key1: &str, key2: &str) -> Option<Yaml>
+{
+    let key1_yaml: Yaml = Yaml::String(key1.to_string());
+    let hash_nested: &Hash = hash[&key1_yaml].as_hash()
+        .expect("Syntax problem in yaml file");
+
+    hash_nested.get(&Yaml::String(key2.to_string())).cloned()
+}
+
 /// Read the content of server{i} section
 pub fn read_server_address(hash_net_config: &Hash, i: u32) -> (String, u16, u16) 
 {
@@ -54,6 +72,84 @@ pub fn read_server_address(hash_net_config: &Hash, i: u32) -> (String, u16, u16)
     (address, port_server, port_client)
 }
 
+/// Read the public key of server i, written in hexadecimal in server{i} section. Returns None if it is
+/// [`UNSET_PUBLIC_KEY`]
+pub fn read_public_key(hash_net_config: &Hash, i: u32) -> Option<PublicKey>
+{
+    let server_i: String = "server".to_owned() + &i.to_string();
+
+    let public_key: String = read_yaml(hash_net_config, &server_i, "public_key")
+        .into_string()
+        .expect("In yaml file, one public key is missing or is not of string type");
+    if public_key == UNSET_PUBLIC_KEY
+    {
+        return None;
+    }
+
+    let bytes: Vec<u8> = hex::decode(public_key)
+        .expect("In yaml file, one public key is not written in hexadecimal");
+
+    let public_key = PublicKey::from_bytes(&bytes)
+        .expect("In yaml file, one public key is not a valid ed25519 public key");
+    Some(public_key)
+}
+
+/// Writes the public key of server i, in hexadecimal, in server{i} section of the configuration file.
+/// The rest of the file, comments included, is kept as it is
+pub fn write_public_key(file: &str, i: u32, public_key: &str) -> Result<(), String>
+{
+    let content = fs::read_to_string(file)
+        .map_err(|e| format!("Could not read the configuration {} : {}", file, e))?;
+    let content = with_public_key(&content, i, public_key)?;
+    fs::write(file, content)
+        .map_err(|e| format!("Could not write the configuration {} : {}", file, e))
+}
+
+/// Returns the content of a configuration file whose server{i} section holds the given public key
+fn with_public_key(content: &str, i: u32, public_key: &str) -> Result<String, String>
+{
+    let mut lines: Vec<String> = content.lines().map(String::from).collect();
+    let section = format!("server{}:", i);
+    let start = lines.iter().position(|line| line.trim_end() == section)
+        .ok_or(format!("The configuration has no section {}", section))?;
+
+    // The section ends with the next line which is not indented
+    let end = lines[start + 1..].iter()
+        .position(|line| !line.is_empty() && !line.starts_with(char::is_whitespace))
+        .map_or(lines.len(), |position| start + 1 + position);
+
+    let entry = format!("  public_key: {}", public_key);
+    match lines[start + 1..end].iter().position(|line| line.trim_start().starts_with("public_key:"))
+    {
+        Some(position) => { lines[start + 1 + position] = entry; }
+        None =>
+        {
+            let last = lines[start + 1..end].iter().rposition(|line| !line.trim().is_empty())
+                .map_or(start, |position| start + 1 + position);
+            lines.insert(last + 1, entry);
+        }
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// Read the voting weight of server i, given by weight in server{i} section. Returns 1 if there is none
+pub fn read_weight(hash_net_config: &Hash, i: u32) -> Weight
+{
+    let server_i: String = "server".to_owned() + &i.to_string();
+
+    match read_optional_yaml(hash_net_config, &server_i, "weight")
+    {
+        None => { 1 }
+        Some(weight) =>
+        {
+            weight.as_i64()
+                .filter(|weight| *weight > 0)
+                .expect("In yaml file, one weight is not a positive int")
+                as Weight
+        }
+    }
+}
+
 /// Read the content of parameters section
 pub fn read_network_parameters(hash_net_config: &Hash) -> u32 
 {
@@ -63,4 +159,98 @@ pub fn read_network_parameters(hash_net_config: &Hash) -> u32
     as u32;
 
     nb_servers
-}
\ No newline at end of file
+}
+
+/// Read the greatest weight of the byzantine servers, given by fault_threshold in parameters section.
+/// Returns None if there is none
+pub fn read_fault_threshold(hash_net_config: &Hash) -> Option<Weight>
+{
+    read_optional_yaml(hash_net_config, "parameters", "fault_threshold").map(|fault_threshold|
+    {
+        fault_threshold.as_i64()
+            .filter(|fault_threshold| *fault_threshold >= 0)
+            .expect("In yaml file, fault_threshold is not a non-negative int")
+            as Weight
+    })
+}
+
+/// Read the protocol used to broadcast transactions, given by broadcast in parameters section.
+/// Returns [`BroadcastKind::Reliable`] if there is none. The sizes of the samples of a gossip broadcast
+/// and their thresholds can be given in parameters section too
+pub fn read_broadcast_kind(hash_net_config: &Hash, nb_servers: u32) -> BroadcastKind
+{
+    let kind = match read_optional_yaml(hash_net_config, "parameters", "broadcast")
+    {
+        None => { BroadcastKind::Reliable }
+        Some(kind) =>
+        {
+            let name: String = kind.into_string()
+                .expect("In yaml file, broadcast is not of string type");
+            BroadcastKind::from_name(&name, nb_servers as usize)
+                .expect("In yaml file, broadcast is neither reliable, consistent, gossip nor dispersal")
+        }
+    };
+
+    match kind
+    {
+        BroadcastKind::Gossip(mut parameters) =>
+        {
+            let mut sizes = [("gossip_sample", &mut parameters.gossip_sample), ("echo_sample", &mut parameters.echo_sample),
+                ("echo_threshold", &mut parameters.echo_threshold), ("ready_sample", &mut parameters.ready_sample),
+                ("ready_threshold", &mut parameters.ready_threshold), ("delivery_sample", &mut parameters.delivery_sample),
+                ("delivery_threshold", &mut parameters.delivery_threshold)];
+            for (key, size) in sizes.iter_mut()
+            {
+                if let Some(value) = read_optional_yaml(hash_net_config, "parameters", key)
+                {
+                    **size = value.as_i64()
+                        .filter(|value| *value >= 0)
+                        .expect("In yaml file, one sample size or threshold is not a non-negative int")
+                        as usize;
+                }
+            }
+            BroadcastKind::Gossip(parameters)
+        }
+        kind => { kind }
+    }
+}
+
+/// Read the lifetime of a broadcast, given in seconds by broadcast_lifetime in parameters section.
+/// Returns [`DEFAULT_BROADCAST_LIFETIME`] if there is none
+pub fn read_broadcast_lifetime(hash_net_config: &Hash) -> Duration
+{
+    match read_optional_yaml(hash_net_config, "parameters", "broadcast_lifetime")
+    {
+        None => { DEFAULT_BROADCAST_LIFETIME }
+        Some(lifetime) =>
+        {
+            let seconds: u64 = lifetime.as_i64()
+                .filter(|seconds| *seconds > 0)
+                .expect("In yaml file, broadcast_lifetime is not a positive int")
+                as u64;
+            Duration::from_secs(seconds)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const CONFIG: &str = "parameters:\n  nb_servers: 2\n\n# First server\nserver1:\n  address: localhost\n  public_key: unset\n\nserver2:\n  address: localhost\n";
+
+    #[test]
+    fn public_keys_are_written_in_their_section()
+    {
+        let written = with_public_key(CONFIG, 1, "ab").unwrap();
+        assert_eq!(written, CONFIG.replace("public_key: unset", "public_key: ab"));
+
+        let written = with_public_key(&written, 2, "cd").unwrap();
+        assert_eq!(written, CONFIG.replace("public_key: unset", "public_key: ab") + "  public_key: cd\n");
+
+        let hash = YamlLoader::load_from_str(&written).unwrap()[0].as_hash().unwrap().clone();
+        assert_eq!(read_yaml(&hash, "server2", "public_key").into_string(), Some(String::from("cd")));
+        assert!(with_public_key(CONFIG, 3, "ef").is_err());
+    }
+}